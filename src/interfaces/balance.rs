@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use alloy::{
+    primitives::{Address, LogData, I256, U256},
+    rpc::types::{
+        trace::parity::{Action, CallType, TransactionTrace},
+        Log,
+    },
+    sol,
+    sol_types::SolEvent,
+};
+
+use super::{AmountMismatch, Swap};
+
+pub mod consts {
+    use alloy::primitives::{address, Address};
+
+    /// placeholder used by most aggregators for the native token
+    pub const ETH: Address = address!("EeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE");
+    pub const WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+}
+
+sol! {
+
+interface ERC20 {
+    event Transfer(address indexed from, address indexed to, uint256 value);
+
+    function transfer(address to, uint256 value) external returns (bool);
+    function transferFrom(address from, address to, uint256 value) external returns (bool);
+}
+
+interface WETH9 {
+    event Deposit(address indexed dst, uint256 wad);
+    event Withdrawal(address indexed src, uint256 wad);
+}
+
+}
+
+/// Decodes `log` as an `E` event. `decode_log` alone doesn't check the
+/// signature, so an `Approval` would pass for a `Transfer` and a WETH
+/// `Withdrawal` for a `Deposit`.
+pub(crate) fn decode_event<E: SolEvent>(log: &Log<LogData>) -> Option<E> {
+    match log.topics().first() {
+        Some(topic) if *topic == E::SIGNATURE_HASH => {
+            E::decode_log(&log.inner, true).ok().map(|log| log.data)
+        }
+        _ => None,
+    }
+}
+
+/// ERC-20 `Transfer` logs along with the emitting token.
+pub fn transfers(logs: &[Log<LogData>]) -> impl Iterator<Item = (Address, ERC20::Transfer)> + '_ {
+    logs.iter().filter(|log| !log.removed).filter_map(|log| {
+        let transfer = decode_event::<ERC20::Transfer>(log)?;
+        Some((log.address(), transfer))
+    })
+}
+
+/// Returns true for the addresses aggregators use to denote the native token.
+pub fn is_eth(token: &Address) -> bool {
+    *token == consts::ETH || token.is_zero()
+}
+
+/// Net token movements of every address touched by a transaction.
+///
+/// ERC-20 balances come from `Transfer` logs (plus WETH `Deposit`/`Withdrawal`,
+/// which mint and burn without a `Transfer`), native ETH from the value of
+/// every successful call in the trace, keyed as [`consts::ETH`].
+#[derive(Debug, Clone, Default)]
+pub struct BalanceDeltas {
+    deltas: HashMap<(Address, Address), I256>,
+}
+
+impl BalanceDeltas {
+    pub fn new(logs: &[Log<LogData>], trace: &[TransactionTrace]) -> Self {
+        let mut deltas = Self::default();
+        deltas.add_logs(logs);
+        deltas.add_trace(trace);
+        deltas
+    }
+
+    pub fn add_logs(&mut self, logs: &[Log<LogData>]) {
+        for log in logs.iter().filter(|log| !log.removed) {
+            let token = log.address();
+            if let Some(transfer) = decode_event::<ERC20::Transfer>(log) {
+                self.transfer(token, transfer.from, transfer.to, transfer.value);
+                continue;
+            }
+            if token != consts::WETH {
+                continue;
+            }
+            if let Some(deposit) = decode_event::<WETH9::Deposit>(log) {
+                self.add(deposit.dst, token, deposit.wad, true);
+            } else if let Some(withdrawal) = decode_event::<WETH9::Withdrawal>(log) {
+                self.add(withdrawal.src, token, withdrawal.wad, false);
+            }
+        }
+    }
+
+    pub fn add_trace(&mut self, trace: &[TransactionTrace]) {
+        // value moved by a reverted frame, or any of its children, never landed
+        let reverted = trace
+            .iter()
+            .filter(|trace| trace.error.is_some())
+            .map(|trace| trace.trace_address.as_slice())
+            .collect::<Vec<_>>();
+        for trace in trace {
+            if reverted
+                .iter()
+                .any(|address| trace.trace_address.starts_with(address))
+            {
+                continue;
+            }
+            match &trace.action {
+                Action::Call(call) => match call.call_type {
+                    CallType::Call | CallType::None => {
+                        self.transfer(consts::ETH, call.from, call.to, call.value)
+                    }
+                    _ => {}
+                },
+                Action::Selfdestruct(action) => self.transfer(
+                    consts::ETH,
+                    action.address,
+                    action.refund_address,
+                    action.balance,
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    fn transfer(&mut self, token: Address, from: Address, to: Address, value: U256) {
+        if value.is_zero() || from == to {
+            return;
+        }
+        self.add(from, token, value, false);
+        self.add(to, token, value, true);
+    }
+
+    fn add(&mut self, owner: Address, token: Address, value: U256, incoming: bool) {
+        let value = I256::from_raw(value);
+        let delta = self.deltas.entry((owner, token)).or_default();
+        *delta = match incoming {
+            true => delta.wrapping_add(value),
+            false => delta.wrapping_sub(value),
+        };
+    }
+
+    /// Net change of `owner`'s balance of `token`, positive when it grew.
+    pub fn delta(&self, owner: &Address, token: &Address) -> I256 {
+        let token = if is_eth(token) { consts::ETH } else { *token };
+        self.deltas
+            .get(&(*owner, token))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn received(&self, owner: &Address, token: &Address) -> U256 {
+        let delta = self.delta(owner, token);
+        match delta.is_positive() {
+            true => delta.into_raw(),
+            false => U256::ZERO,
+        }
+    }
+
+    pub fn spent(&self, owner: &Address, token: &Address) -> U256 {
+        let delta = self.delta(owner, token);
+        match delta.is_negative() {
+            true => delta.unsigned_abs(),
+            false => U256::ZERO,
+        }
+    }
+
+    /// Every token whose balance changed for `owner`.
+    pub fn of(&self, owner: &Address) -> impl Iterator<Item = (Address, I256)> + '_ {
+        let owner = *owner;
+        self.deltas
+            .iter()
+            .filter(move |((address, _), delta)| *address == owner && !delta.is_zero())
+            .map(|((_, token), delta)| (*token, *delta))
+    }
+
+    /// The only token `owner` paid out, if there is exactly one.
+    pub fn spent_token(&self, owner: &Address) -> Option<Address> {
+        let mut tokens = self.of(owner).filter(|(_, delta)| delta.is_negative());
+        match (tokens.next(), tokens.next()) {
+            (Some((token, _)), None) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// The only token `owner` was paid in, if there is exactly one.
+    pub fn received_token(&self, owner: &Address) -> Option<Address> {
+        let mut tokens = self.of(owner).filter(|(_, delta)| delta.is_positive());
+        match (tokens.next(), tokens.next()) {
+            (Some((token, _)), None) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Cross-checks the protocol-derived amounts of `swap` against what its
+    /// sender actually paid and its recipient actually got.
+    ///
+    /// Decoded amounts are kept; the balance changes only fill in amounts the
+    /// decoder left at zero. Any disagreement, as with fee-on-transfer tokens
+    /// or fees taken out of the output, is recorded in `swap.mismatches`.
+    ///
+    /// WETH is reported as ETH when the user side of the swap was wrapped or
+    /// unwrapped by the router.
+    pub fn settle(&self, swap: &mut Swap) {
+        let (from, to) = (swap.from_address, swap.to_address);
+        for token in [&mut swap.input_token, &mut swap.output_token] {
+            if is_eth(token) {
+                *token = consts::ETH;
+            }
+        }
+        if swap.input_token == consts::WETH
+            && self.spent(&from, &consts::WETH).is_zero()
+            && !self.spent(&from, &consts::ETH).is_zero()
+        {
+            swap.input_token = consts::ETH;
+        }
+        if swap.output_token == consts::WETH
+            && self.received(&to, &consts::WETH).is_zero()
+            && !self.received(&to, &consts::ETH).is_zero()
+        {
+            swap.output_token = consts::ETH;
+        }
+
+        let spent = self.spent(&from, &swap.input_token);
+        let received = self.received(&to, &swap.output_token);
+        for (address, token, amount, settled) in [
+            (from, swap.input_token, &mut swap.input_amount, spent),
+            (to, swap.output_token, &mut swap.output_amount, received),
+        ] {
+            if amount.is_zero() {
+                *amount = settled;
+            } else if !settled.is_zero() && *amount != settled {
+                swap.mismatches.push(AmountMismatch {
+                    address,
+                    token,
+                    decoded: *amount,
+                    settled,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    const USER: Address = address!("00000000000000000000000000000000000000aa");
+    const POOL: Address = address!("00000000000000000000000000000000000000bb");
+    const FEE: Address = address!("00000000000000000000000000000000000000cc");
+
+    fn log(address: Address, data: LogData) -> Log<LogData> {
+        Log {
+            inner: alloy::primitives::Log { address, data },
+            ..Default::default()
+        }
+    }
+
+    fn transfer(token: Address, from: Address, to: Address, value: u64) -> Log<LogData> {
+        let value = U256::from(value);
+        log(token, ERC20::Transfer { from, to, value }.encode_log_data())
+    }
+
+    /// The user sells 100 USDC, 1 of which goes to a fee, for 7 WETH.
+    fn deltas() -> BalanceDeltas {
+        let logs = [
+            transfer(USDC, USER, POOL, 99),
+            transfer(USDC, USER, FEE, 1),
+            transfer(consts::WETH, POOL, USER, 7),
+        ];
+        BalanceDeltas::new(&logs, &[])
+    }
+
+    #[test]
+    fn transfers_net_out() {
+        let deltas = deltas();
+        assert_eq!(deltas.spent(&USER, &USDC), U256::from(100));
+        assert_eq!(deltas.received(&USER, &consts::WETH), U256::from(7));
+        assert_eq!(deltas.received(&USER, &USDC), U256::ZERO);
+        assert_eq!(deltas.delta(&POOL, &USDC), I256::try_from(99).unwrap());
+        assert_eq!(deltas.spent_token(&USER), Some(USDC));
+        assert_eq!(deltas.received_token(&USER), Some(consts::WETH));
+        assert_eq!(deltas.received_token(&POOL), Some(USDC));
+    }
+
+    #[test]
+    fn weth_deposits_and_withdrawals() {
+        let deposit = WETH9::Deposit {
+            dst: USER,
+            wad: U256::from(5),
+        };
+        let withdrawal = WETH9::Withdrawal {
+            src: USER,
+            wad: U256::from(2),
+        };
+        let logs = [
+            log(consts::WETH, deposit.encode_log_data()),
+            log(consts::WETH, withdrawal.encode_log_data()),
+            // only WETH's are counted
+            log(USDC, deposit.encode_log_data()),
+        ];
+        let deltas = BalanceDeltas::new(&logs, &[]);
+        assert_eq!(deltas.received(&USER, &consts::WETH), U256::from(3));
+        assert_eq!(deltas.delta(&USER, &USDC), I256::ZERO);
+    }
+
+    #[test]
+    fn settle_keeps_decoded_amounts() {
+        let mut swap = Swap::new(USER, USER, USDC, consts::WETH, U256::from(99), U256::ZERO);
+        deltas().settle(&mut swap);
+        assert_eq!(swap.input_amount, U256::from(99));
+        // filled in where the decoder had nothing
+        assert_eq!(swap.output_amount, U256::from(7));
+        assert_eq!(swap.mismatches.len(), 1);
+        let mismatch = &swap.mismatches[0];
+        assert_eq!((mismatch.address, mismatch.token), (USER, USDC));
+        assert_eq!(mismatch.decoded, U256::from(99));
+        assert_eq!(mismatch.settled, U256::from(100));
+    }
+}
@@ -0,0 +1,167 @@
+use alloy::{
+    primitives::{Address, U256},
+    sol,
+    sol_types::{SolCall, SolEvent},
+};
+
+use super::{
+    balance::{consts::ETH, decode_event, is_eth},
+    DecodeError, Decoder, DecoderContext, Fee, Interaction, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "CoW Protocol GPv2Settlement";
+    pub const SETTLEMENT: Address = address!("9008D19f58AAbD9eD0D60971565AA8510560ab41");
+}
+
+sol! {
+
+// cast interface 0x9008D19f58AAbD9eD0D60971565AA8510560ab41
+interface GPv2Settlement {
+    struct TradeData {
+        uint256 sellTokenIndex;
+        uint256 buyTokenIndex;
+        address receiver;
+        uint256 sellAmount;
+        uint256 buyAmount;
+        uint32 validTo;
+        bytes32 appData;
+        uint256 feeAmount;
+        uint256 flags;
+        uint256 executedAmount;
+        bytes signature;
+    }
+
+    struct InteractionData {
+        address target;
+        uint256 value;
+        bytes callData;
+    }
+
+    event Interaction(address indexed target, uint256 value, bytes4 selector);
+    event OrderInvalidated(address indexed owner, bytes orderUid);
+    event PreSignature(address indexed owner, bytes orderUid, bool signed);
+    event Settlement(address indexed solver);
+    event Trade(
+        address indexed owner,
+        address sellToken,
+        address buyToken,
+        uint256 sellAmount,
+        uint256 buyAmount,
+        uint256 feeAmount,
+        bytes orderUid
+    );
+
+    function settle(
+        address[] calldata tokens,
+        uint256[] calldata clearingPrices,
+        TradeData[] calldata trades,
+        InteractionData[][3] calldata interactions
+    ) external;
+}
+
+}
+
+/// Batch auctions settled by a solver: every trade is a user's own swap, the
+/// liquidity the solver tapped for the batch is listed as interactions.
+pub struct DecoderGPv2Settlement {}
+
+impl DecoderGPv2Settlement {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderGPv2Settlement {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::SETTLEMENT]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        vec![GPv2Settlement::settleCall::SELECTOR]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let call = GPv2Settlement::settleCall::abi_decode(&context.tx().input, true)?;
+        // one `Trade` per trade, emitted in calldata order
+        let events = context
+            .logs()?
+            .iter()
+            .filter(|log| log.address() == consts::SETTLEMENT && !log.removed)
+            .filter_map(decode_event::<GPv2Settlement::Trade>)
+            .collect::<Vec<_>>();
+        if events.len() != call.trades.len() {
+            return Err(DecodeError::MissingLogs(consts::SETTLEMENT));
+        }
+
+        call.trades
+            .iter()
+            .zip(events)
+            .map(|(trade, event)| {
+                let token = |index: U256| {
+                    let token = call
+                        .tokens
+                        .get(index.saturating_to::<usize>())
+                        .copied()
+                        .ok_or(DecodeError::UnresolvedToken(event.owner))?;
+                    Ok::<_, DecodeError>(match is_eth(&token) {
+                        true => ETH,
+                        false => token,
+                    })
+                };
+                let input_token = token(trade.sellTokenIndex)?;
+                let to_address = match trade.receiver {
+                    Address::ZERO => event.owner,
+                    receiver => receiver,
+                };
+                // the fee is taken in the sell token and kept by the
+                // settlement contract
+                let fees = match event.feeAmount.is_zero() {
+                    true => vec![],
+                    false => vec![Fee {
+                        recipient: consts::SETTLEMENT,
+                        token: input_token,
+                        amount: event.feeAmount,
+                    }],
+                };
+                Ok(Swap {
+                    fees,
+                    ..Swap::new(
+                        event.owner,
+                        to_address,
+                        input_token,
+                        token(trade.buyTokenIndex)?,
+                        // executed sell amount, fee included
+                        event.sellAmount,
+                        event.buyAmount,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn interactions(&self, context: &DecoderContext) -> Result<Vec<Interaction>, DecodeError> {
+        let call = GPv2Settlement::settleCall::abi_decode(&context.tx().input, true)?;
+        Ok(call
+            .interactions
+            .into_iter()
+            .enumerate()
+            .flat_map(|(stage, interactions)| {
+                interactions
+                    .into_iter()
+                    .map(move |interaction| Interaction {
+                        stage: stage as u8,
+                        target: interaction.target,
+                        value: interaction.value,
+                        input: interaction.callData,
+                    })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,44 @@
+use alloy::primitives::{hex, Address, TxHash};
+
+/// Why a transaction could not be decoded.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("creation transaction is not supported")]
+    ContractCreation,
+    #[error("insufficient calldata")]
+    CalldataTooShort,
+    #[error("unsupported selector: 0x{}", hex::encode(.0))]
+    UnsupportedSelector([u8; 4]),
+    #[error("unsupported command: {0:#04x}")]
+    UnsupportedCommand(u8),
+    #[error("unsupported aggregator: {0}")]
+    UnsupportedAggregator(String),
+    #[error("unsupported pool protocol: {0}")]
+    UnsupportedProtocol(u32),
+    #[error("multiple swap logs for pool {0}")]
+    AmbiguousPoolLogs(Address),
+    #[error("no swap log for pool {0}")]
+    MissingLogs(Address),
+    #[error("no return data for the call to {0}")]
+    MissingOutput(Address),
+    #[error("no pair traded {0} for {1}")]
+    UnresolvedPair(Address, Address),
+    #[error("no path found")]
+    EmptyPath,
+    #[error("curve coin index out of range for pool {0}")]
+    InvalidCoinIndex(Address),
+    #[error("could not resolve the swapped token from balance changes of {0}")]
+    UnresolvedToken(Address),
+    #[error("transaction reverted: {0}")]
+    RevertedTx(TxHash),
+    #[error("abi decoding failed: {0}")]
+    Abi(#[from] alloy::sol_types::Error),
+    #[error("rpc error: {0}")]
+    RpcError(String),
+}
+
+impl DecodeError {
+    pub(crate) fn rpc(err: eyre::Report) -> Self {
+        Self::RpcError(format!("{:#}", err))
+    }
+}
@@ -0,0 +1,156 @@
+use alloy::{
+    primitives::Address,
+    providers::network::TransactionResponse,
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface},
+};
+
+use super::{balance::decode_event, DecodeError, Decoder, DecoderContext, Fee, Swap};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "KyberSwap MetaAggregationRouterV2";
+    pub const ROUTER: Address = address!("6131B5fae19EA4f9D964eAc0408E4408b66337b5");
+
+    // SwapDescriptionV2.flags
+    pub const FEE_ON_DST: usize = 6;
+    pub const FEE_IN_BPS: usize = 7;
+}
+
+sol! {
+
+// cast interface 0x6131B5fae19EA4f9D964eAc0408E4408b66337b5
+interface MetaAggregationRouterV2 {
+    struct SwapDescriptionV2 {
+        address srcToken;
+        address dstToken;
+        address[] srcReceivers;
+        uint256[] srcAmounts;
+        address[] feeReceivers;
+        uint256[] feeAmounts;
+        address dstReceiver;
+        uint256 amount;
+        uint256 minReturnAmount;
+        uint256 flags;
+        bytes permit;
+    }
+
+    struct SwapExecutionParams {
+        address callTarget;
+        address approveTarget;
+        bytes targetData;
+        SwapDescriptionV2 desc;
+        bytes clientData;
+    }
+
+    event ClientData(bytes clientData);
+    event Error(string reason);
+    event Exchange(address pair, uint256 amountOut, address output);
+    event Fee(address token, uint256 totalAmount, uint256 totalFee, address[] recipients, uint256[] amounts, bool isBps);
+    event Swapped(
+        address sender,
+        address srcToken,
+        address dstToken,
+        address dstReceiver,
+        uint256 spentAmount,
+        uint256 returnAmount
+    );
+
+    function swap(SwapExecutionParams memory execution)
+        external
+        payable
+        returns (uint256 returnAmount, uint256 gasUsed);
+    function swapGeneric(SwapExecutionParams memory execution)
+        external
+        payable
+        returns (uint256 returnAmount, uint256 gasUsed);
+    function swapSimpleMode(
+        address caller,
+        SwapDescriptionV2 memory desc,
+        bytes calldata executorData,
+        bytes calldata clientData
+    ) external returns (uint256 returnAmount, uint256 gasUsed);
+}
+
+}
+
+pub struct DecoderKyberSwap {}
+
+impl DecoderKyberSwap {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderKyberSwap {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use MetaAggregationRouterV2 as C;
+        vec![
+            C::swapCall::SELECTOR,
+            C::swapGenericCall::SELECTOR,
+            C::swapSimpleModeCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use MetaAggregationRouterV2::MetaAggregationRouterV2Calls as C;
+        let tx = context.tx();
+
+        let desc = match C::abi_decode(&tx.input, true)? {
+            C::swap(call) => call.execution.desc,
+            C::swapGeneric(call) => call.execution.desc,
+            C::swapSimpleMode(call) => call.desc,
+        };
+        let swapped = context
+            .logs()?
+            .iter()
+            .filter(|log| log.address() == consts::ROUTER && !log.removed)
+            .find_map(decode_event::<MetaAggregationRouterV2::Swapped>)
+            .ok_or(DecodeError::MissingLogs(consts::ROUTER))?;
+
+        let deltas = context.balance_deltas()?;
+        let token = match desc.flags.bit(consts::FEE_ON_DST) {
+            true => desc.dstToken,
+            false => desc.srcToken,
+        };
+        let fees = desc
+            .feeReceivers
+            .iter()
+            .zip(&desc.feeAmounts)
+            .map(|(recipient, amount)| Fee {
+                recipient: *recipient,
+                token,
+                // a share of the charged side in basis points, or a fixed
+                // amount of it
+                amount: match desc.flags.bit(consts::FEE_IN_BPS) {
+                    true => deltas.received(recipient, &token),
+                    false => *amount,
+                },
+            })
+            .filter(|fee| !fee.amount.is_zero())
+            .collect();
+
+        let mut swap = Swap {
+            fees,
+            ..Swap::new(
+                tx.from(),
+                swapped.dstReceiver,
+                swapped.srcToken,
+                swapped.dstToken,
+                swapped.spentAmount,
+                swapped.returnAmount,
+            )
+        };
+        deltas.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
@@ -1,15 +1,34 @@
+use std::{cell::OnceCell, collections::HashMap};
+
 use alloy::{
-    primitives::Address, providers::network::TransactionResponse, rpc::types::Transaction, sol, sol_types::{SolCall, SolInterface}
+    primitives::{keccak256, Address, Bytes, B256, U256},
+    providers::network::TransactionResponse,
+    rpc::types::{
+        trace::parity::{Action, CallType},
+        Filter, Log,
+    },
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface, SolValue},
 };
-use eyre::eyre;
 
-use super::Decoder;
+use super::{
+    balance::decode_event, extract_selector, DecodeError, Decoder, DecoderContext,
+    DecoderKyberSwap, DecoderOneInchV4, DecoderOneInchV5, DecoderOpenOcean, DecoderParaSwapV5,
+    DecoderZeroEx, Fee, Swap,
+};
 
 mod consts {
-    use alloy::primitives::{address, b256, Address, B256};
+    use alloy::primitives::{address, Address};
 
     pub const NAME: &str = "MetaMask Swap Router";
     pub const ROUTER: Address = address!("881d40237659c251811cec9c364ef91dc08d300c");
+    /// blocks per `eth_getLogs` request when replaying the adapters
+    pub const LOGS_PAGE: u64 = 100_000;
+
+    /// holds the user's tokens and delegatecalls the adapters, so every
+    /// aggregator call is made from here
+    pub const SPENDER: Address = address!("74de5d4FCbf63E00296fd95d33236B9794016631");
+    pub const FEE_WALLET: Address = address!("f326e4dE8F66A0BDC0970b79E0924e33c79f1915");
 }
 
 sol! {
@@ -30,9 +49,15 @@ interface MetaSwap {
 
 // Spec:
 // swap_router = 0x881d40237659c251811cec9c364ef91dc08d300c
-// airswap_spender =
-// swap_router.swap -> airswap_spender.swap -> adapter
+// spender = 0x74de5d4fcbf63e00296fd95d33236b9794016631
+// swap_router.swap -> spender.swap -> delegatecall adapter -> aggregator
 // selector = predefined
+//
+// The adapter receives `abi.encodePacked(selector, abi.encode(msg.sender),
+// adapter.data, data)`, takes MetaMask's fee and calls the aggregator with
+// the calldata carried in `data`. The fee wallet is paid before or after the
+// aggregator call depending on the adapter, so the fee is read from its
+// balance change rather than from `data`.
 
 // {'openOceanFeeDynamic': 44488,
 //              'oneInchV5FeeDynamic': 785774,
@@ -49,11 +74,65 @@ interface MetaSwap {
 //              'oneInchV3': 3,
 //              'paraswapV4': 1}
 
-pub struct DecoderMetaMaskSwapRouter {}
+pub struct DecoderMetaMaskSwapRouter {
+    /// fetched on first use, up to the chain head at that time
+    adapters: OnceCell<AdapterRegistry>,
+    kyber_swap: DecoderKyberSwap,
+    one_inch_v4: DecoderOneInchV4,
+    one_inch_v5: DecoderOneInchV5,
+    open_ocean: DecoderOpenOcean,
+    paraswap_v5: DecoderParaSwapV5,
+    zero_ex: DecoderZeroEx,
+}
 
 impl DecoderMetaMaskSwapRouter {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            adapters: OnceCell::new(),
+            kyber_swap: DecoderKyberSwap::new(),
+            one_inch_v4: DecoderOneInchV4::new(),
+            one_inch_v5: DecoderOneInchV5::new(),
+            open_ocean: DecoderOpenOcean::new(),
+            paraswap_v5: DecoderParaSwapV5::new(),
+            zero_ex: DecoderZeroEx::new(),
+        }
+    }
+
+    /// Replays the router's adapter logs from its deployment to the chain
+    /// head, `LOGS_PAGE` blocks per request.
+    fn adapters(&self, context: &DecoderContext) -> Result<&AdapterRegistry, DecodeError> {
+        if self.adapters.get().is_none() {
+            let latest = context.block_number()?;
+            let filter = Filter::new().address(consts::ROUTER).event_signature(vec![
+                MetaSwap::AdapterSet::SIGNATURE_HASH,
+                MetaSwap::AdapterRemoved::SIGNATURE_HASH,
+            ]);
+            let mut logs = vec![];
+            let mut from = context.deployment_block(&consts::ROUTER, latest)?;
+            while from <= latest {
+                let to = latest.min(from + consts::LOGS_PAGE - 1);
+                logs.extend(context.get_logs(&filter.clone().from_block(from).to_block(to))?);
+                from = to + 1;
+            }
+            self.adapters.set(AdapterRegistry::new(&logs)).unwrap();
+        }
+        Ok(self.adapters.get().unwrap())
+    }
+
+    /// The decoder of the aggregator an adapter calls, `None` for the
+    /// adapters whose venue has no decoder here.
+    fn aggregator(&self, aggregator_id: &str) -> Option<&dyn Decoder> {
+        match aggregator_id {
+            "oneInchV4FeeDynamic" => Some(&self.one_inch_v4),
+            "oneInchV5FeeDynamic" => Some(&self.one_inch_v5),
+            "paraswapV5FeeDynamic" => Some(&self.paraswap_v5),
+            "0xFeeDynamic" => Some(&self.zero_ex),
+            "kyberSwapFeeDynamic" => Some(&self.kyber_swap),
+            "openOceanFeeDynamic" => Some(&self.open_ocean),
+            // airswapLight4FeeDynamicFixed, pmmFeeDynamicv4, hashFlowFeeDynamic,
+            // bebopMultiFeeDynamic, ...
+            _ => None,
+        }
     }
 }
 
@@ -67,26 +146,225 @@ impl Decoder for DecoderMetaMaskSwapRouter {
     }
 
     fn supported_selectors(&self) -> Vec<[u8; 4]> {
-        vec![MetaSwap::swapCall::SELECTOR]
+        vec![
+            MetaSwap::swapCall::SELECTOR,
+            MetaSwap::swapUsingGasTokenCall::SELECTOR,
+        ]
     }
 
-    fn decode(&self, context: &super::DecoderContext) -> eyre::Result<super::Swap> {
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use MetaSwap::MetaSwapCalls as C;
         let tx = context.tx();
 
-        match MetaSwap::MetaSwapCalls::abi_decode(&tx.input, true)? {
-            MetaSwap::MetaSwapCalls::swap(call) => {
-                let input_token = call.tokenFrom;
-                let input_amount = call.amount;
-                let from_address = tx.from();
-                let to_address = tx.from();
-
-                match call.aggregatorId.as_str() {
-                    "oneInchV5FeeDynamic" => {}
-                    _ => return Err(eyre!("MetaMask Swap Router: unspported aggregator: ")),
+        // swapUsingGasToken only burns the user's gas token on top
+        let (aggregator_id, token_from, data) = match C::abi_decode(&tx.input, true)? {
+            C::swap(call) => (call.aggregatorId, call.tokenFrom, call.data),
+            C::swapUsingGasToken(call) => (call.aggregatorId, call.tokenFrom, call.data),
+        };
+        let position = (
+            tx.block_number.unwrap_or(u64::MAX),
+            tx.transaction_index.unwrap_or(u64::MAX),
+        );
+        let unsupported = || DecodeError::UnsupportedAggregator(aggregator_id.clone());
+        let adapter = self
+            .adapters(context)?
+            .adapter(&aggregator_id, position)
+            .ok_or_else(unsupported)?;
+        // adapters that fill the swap themselves, or a venue this aggregator
+        // reached without its usual router, aren't guessed from balances
+        let decoder = self.aggregator(&aggregator_id).ok_or_else(unsupported)?;
+        let inner = aggregator_call(context, adapter, decoder)?.ok_or_else(unsupported)?;
+        let mut swaps = decoder.decode(&inner)?;
+        let fees = fees(context)?;
+        for swap in &mut swaps {
+            // the spender only passes the tokens through
+            for address in [&mut swap.from_address, &mut swap.to_address] {
+                if *address == consts::SPENDER {
+                    *address = tx.from();
                 }
             }
-            _ => return Err(eyre!("MetaMask Swap Router: unsupported selector: ")),
+            // what the user paid and got differs by MetaMask's fee
+            context.balance_deltas()?.settle(swap);
+            swap.fees.extend(fees.iter().cloned());
+        }
+        Ok(swaps)
+    }
+}
+
+/// What `setAdapter` installed under an aggregator id.
+#[derive(Debug, Clone)]
+struct Adapter {
+    addr: Address,
+    selector: [u8; 4],
+    /// passed to the adapter ahead of the swap's own `data`
+    data: Bytes,
+}
+
+/// The router's adapters over time, replayed from its `AdapterSet` and
+/// `AdapterRemoved` logs. Aggregator ids are indexed strings, so the logs
+/// only carry their hash.
+#[derive(Debug, Default)]
+struct AdapterRegistry {
+    /// every change of an id, in chain order
+    changes: HashMap<B256, Vec<AdapterChange>>,
+}
+
+#[derive(Debug)]
+struct AdapterChange {
+    /// (block, transaction index)
+    position: (u64, u64),
+    /// `None` once removed
+    adapter: Option<Adapter>,
+}
+
+impl AdapterRegistry {
+    fn new(logs: &[Log]) -> Self {
+        let mut registry = Self::default();
+        for log in logs.iter().filter(|log| !log.removed) {
+            let (aggregator_id, adapter) =
+                if let Some(set) = decode_event::<MetaSwap::AdapterSet>(log) {
+                    let adapter = Adapter {
+                        addr: set.addr,
+                        selector: set.selector.0,
+                        data: set.data,
+                    };
+                    (set.aggregatorId, Some(adapter))
+                } else if let Some(removed) = decode_event::<MetaSwap::AdapterRemoved>(log) {
+                    (removed.aggregatorId, None)
+                } else {
+                    continue;
+                };
+            let position = (
+                log.block_number.unwrap_or_default(),
+                log.transaction_index.unwrap_or_default(),
+            );
+            registry
+                .changes
+                .entry(aggregator_id)
+                .or_default()
+                .push(AdapterChange { position, adapter });
+        }
+        registry
+    }
+
+    /// The adapter installed for `aggregator_id` when the transaction at
+    /// `position` ran, `None` if there was none or it had been removed.
+    fn adapter(&self, aggregator_id: &str, position: (u64, u64)) -> Option<&Adapter> {
+        self.changes
+            .get(&keccak256(aggregator_id))?
+            .iter()
+            .take_while(|change| change.position < position)
+            .last()?
+            .adapter
+            .as_ref()
+    }
+}
+
+/// The aggregator call made by `adapter` on behalf of the spender, as a
+/// context the aggregator's decoder can take.
+fn aggregator_call(
+    context: &DecoderContext,
+    adapter: &Adapter,
+    decoder: &dyn Decoder,
+) -> Result<Option<DecoderContext>, DecodeError> {
+    let trace = &context.trace()?.trace;
+    // the spender delegatecalls the adapter with `selector ++
+    // abi.encode(sender) ++ adapter.data ++ data`
+    let frame = trace.iter().find(|trace| match &trace.action {
+        Action::Call(call) => {
+            call.call_type == CallType::DelegateCall
+                && call.from == consts::SPENDER
+                && call.to == adapter.addr
+                && call.input.starts_with(&adapter.selector)
+                && call.input.get(36..36 + adapter.data.len()) == Some(&adapter.data[..])
+        }
+        _ => false,
+    });
+    let frame = match frame {
+        Some(frame) => &frame.trace_address,
+        None => return Ok(None),
+    };
+
+    let routers = decoder.supported_address();
+    let selectors = decoder.supported_selectors();
+    let call = trace
+        .iter()
+        .filter(|trace| trace.error.is_none() && trace.trace_address.starts_with(frame))
+        .find_map(|trace| match &trace.action {
+            Action::Call(call)
+                if call.call_type == CallType::Call
+                    && call.from == consts::SPENDER
+                    && routers.contains(&call.to)
+                    && extract_selector(&call.input)
+                        .is_ok_and(|selector| selectors.contains(&selector)) =>
+            {
+                Some(call)
+            }
+            _ => None,
+        });
+    Ok(
+        call.map(|call| {
+            context.with_call(consts::SPENDER, call.to, call.input.clone(), call.value)
+        }),
+    )
+}
+
+/// Everything the MetaMask fee wallet received in the transaction.
+fn fees(context: &DecoderContext) -> Result<Vec<Fee>, DecodeError> {
+    Ok(context
+        .balance_deltas()?
+        .of(&consts::FEE_WALLET)
+        .filter(|(_, delta)| delta.is_positive())
+        .map(|(token, delta)| Fee {
+            recipient: consts::FEE_WALLET,
+            token,
+            amount: delta.into_raw(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, bytes};
+
+    use super::*;
+
+    const ADAPTER: Address = address!("5F27ab6C6Df2a9bEbA9f7C8b9E1eA17ec1D4D4C4");
+
+    fn log(event: &impl SolEvent, block: u64, index: u64) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: consts::ROUTER,
+                data: event.encode_log_data(),
+            },
+            block_number: Some(block),
+            transaction_index: Some(index),
+            ..Default::default()
         }
-        todo!()
+    }
+
+    #[test]
+    fn adapter_history() {
+        let id = "oneInchV5FeeDynamic";
+        let set = MetaSwap::AdapterSet {
+            aggregatorId: keccak256(id),
+            addr: ADAPTER,
+            selector: [0x92, 0xf5, 0xf0, 0x37].into(),
+            data: bytes!("0102"),
+        };
+        let removed = MetaSwap::AdapterRemoved {
+            aggregatorId: keccak256(id),
+        };
+        let registry = AdapterRegistry::new(&[log(&set, 10, 2), log(&removed, 20, 0)]);
+
+        // only transactions after the change see it
+        assert!(registry.adapter(id, (10, 2)).is_none());
+        let adapter = registry.adapter(id, (10, 3)).unwrap();
+        assert_eq!(adapter.addr, ADAPTER);
+        assert_eq!(adapter.selector, [0x92, 0xf5, 0xf0, 0x37]);
+        assert_eq!(adapter.data, bytes!("0102"));
+        assert!(registry.adapter(id, (19, 100)).is_some());
+        assert!(registry.adapter(id, (20, 1)).is_none());
+        assert!(registry.adapter("paraswapV5", (15, 0)).is_none());
     }
 }
@@ -1,29 +1,53 @@
+pub mod balance;
+mod cow;
+mod error;
+mod kyberswap;
 mod metamask;
 mod oneinch_v4;
 mod oneinch_v5;
+mod oneinch_v6;
+mod openocean;
 mod paraswap_v5;
+pub mod registry;
+mod uniswap_v2;
 mod uniswap_v3;
 pub mod universal_router;
 mod zerox;
 
-use std::{cell::{Cell, OnceCell}, future::IntoFuture, io::Read, sync::Once};
+use std::{cell::{Cell, OnceCell}, future::IntoFuture, io::Read, rc::Rc, sync::Once};
 
 use alloy::{
     eips::BlockNumberOrTag,
-    primitives::{Address, LogData, TxHash, U256},
+    primitives::{Address, Bytes, LogData, TxHash, B128, B256, U256},
     providers::{ext::TraceApi, network::TransactionResponse, Provider, ProviderBuilder},
     rpc::types::{
         trace::{
             geth::TraceResult,
-            parity::{TraceResults, TraceType},
-        }, Index, Log, Transaction, TransactionReceipt
+            parity::{Action, TraceOutput, TraceResults, TraceType},
+        }, Filter, Index, Log, Transaction, TransactionReceipt
     },
 };
+use balance::BalanceDeltas;
+pub use cow::DecoderGPv2Settlement;
+pub use error::DecodeError;
+pub use metamask::DecoderMetaMaskSwapRouter;
 use eyre::{eyre, OptionExt};
+pub use kyberswap::DecoderKyberSwap;
+pub use oneinch_v4::DecoderOneInchV4;
+pub use oneinch_v5::DecoderOneInchV5;
+pub use oneinch_v6::DecoderOneInchV6;
+pub use openocean::DecoderOpenOcean;
 pub use paraswap_v5::*;
+pub use registry::*;
+use serde::{Deserialize, Serialize};
+pub use uniswap_v2::DecoderUniswapV2;
 pub use uniswap_v3::*;
 pub use universal_router::*;
+pub use zerox::{DecoderZeroEx, DecoderZeroExProxy};
 
+/// The user intent of a swap: what `from_address` paid and what `to_address`
+/// received, with the route that was taken in `legs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Swap {
     pub from_address: Address,
     pub to_address: Address,
@@ -31,9 +55,159 @@ pub struct Swap {
     pub output_token: Address,
     pub input_amount: U256,
     pub output_amount: U256,
+    /// pool-level hops, in execution order
+    pub legs: Vec<SwapLeg>,
+    /// integrator the aggregator credited for the swap, if any
+    pub partner: Option<Partner>,
+    /// how the aggregator split the swap between exchanges, when the
+    /// calldata says
+    pub route: Vec<RouteSplit>,
+    /// fees paid out of the swap to third parties
+    pub fees: Vec<Fee>,
+    /// decoded amounts the balance changes of the sender or recipient
+    /// disagree with
+    pub mismatches: Vec<AmountMismatch>,
 }
 
-pub async fn get_tx(url: &String, hash: &TxHash) -> eyre::Result<Transaction> {
+impl Swap {
+    /// A swap with no legs, partner, route, fees or mismatches.
+    pub fn new(
+        from_address: Address,
+        to_address: Address,
+        input_token: Address,
+        output_token: Address,
+        input_amount: U256,
+        output_amount: U256,
+    ) -> Self {
+        Self {
+            from_address,
+            to_address,
+            input_token,
+            output_token,
+            input_amount,
+            output_amount,
+            legs: vec![],
+            partner: None,
+            route: vec![],
+            fees: vec![],
+            mismatches: vec![],
+        }
+    }
+}
+
+/// A fee taken by an aggregator or integrator, separate from pool fees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fee {
+    pub recipient: Address,
+    pub token: Address,
+    pub amount: U256,
+}
+
+/// An amount decoded from calldata or logs that differs from the balance
+/// change of `address`, e.g. for fee-on-transfer tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AmountMismatch {
+    pub address: Address,
+    pub token: Address,
+    pub decoded: U256,
+    pub settled: U256,
+}
+
+/// Referral data some aggregators attach to a swap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Partner {
+    pub address: Address,
+    /// fee as encoded by the aggregator, flags included
+    pub fee_percent: U256,
+    pub uuid: B128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Protocol {
+    UniswapV2,
+    UniswapV3,
+    /// `i` and `j` are the pool's indices of the sold and bought coins
+    Curve {
+        i: u8,
+        j: u8,
+    },
+    /// pools share the Vault, `pool_id` starts with the pool's address
+    BalancerV2 {
+        pool_id: B256,
+    },
+}
+
+/// One edge of an aggregator's route graph: the share of a step's input that
+/// was handed to `exchange`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteSplit {
+    /// index of the top-level path, for routes split before the first step
+    pub path: usize,
+    /// index of the step within `path`
+    pub step: usize,
+    /// share of the swap's input sent down `path`, in basis points
+    pub path_percent: u32,
+    /// share of the step's input routed through `exchange`, in basis points
+    pub percent: u32,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub exchange: Address,
+    /// pool-level hops, when the exchange's calldata is understood
+    pub legs: Vec<SwapLeg>,
+}
+
+/// A call made by a batch settlement on the solver's behalf, not tied to any
+/// one swap of the batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    /// 0 before the sell amounts are pulled in, 1 between, 2 after the buy
+    /// amounts are paid out
+    pub stage: u8,
+    pub target: Address,
+    pub value: U256,
+    pub input: Bytes,
+}
+
+/// NFTs bought through a marketplace, reported alongside the fungible swaps
+/// of the same transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NftPurchase {
+    pub marketplace: Address,
+    pub collection: Address,
+    pub token_ids: Vec<U256>,
+    pub payment_token: Address,
+    /// what the marketplace kept, refunds excluded; a purchase spanning
+    /// several collections reports it on the first of them only
+    pub payment_amount: U256,
+    pub recipient: Address,
+}
+
+/// A single hop through one pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapLeg {
+    pub protocol: Protocol,
+    pub pool: Address,
+    /// fee tier in hundredths of a bip, 0 when the pool has no fixed tier
+    pub fee: u32,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub zero_for_one: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedTx {
+    pub tx_hash: TxHash,
+    pub block_number: Option<u64>,
+    pub transaction_index: Option<u64>,
+    pub decoder: String,
+    pub swaps: Vec<Swap>,
+    pub interactions: Vec<Interaction>,
+    pub nft_purchases: Vec<NftPurchase>,
+}
+
+pub async fn get_tx(url: &str, hash: &TxHash) -> eyre::Result<Transaction> {
     let provider = ProviderBuilder::new().on_http(url.parse()?);
     let receipt = provider
         .get_transaction_by_hash(hash.to_owned())
@@ -42,7 +216,7 @@ pub async fn get_tx(url: &String, hash: &TxHash) -> eyre::Result<Transaction> {
     Ok(receipt)
 }
 
-pub async fn get_tx_trace(url: &String, hash: &TxHash) -> eyre::Result<TraceResults> {
+pub async fn get_tx_trace(url: &str, hash: &TxHash) -> eyre::Result<TraceResults> {
     let provider = ProviderBuilder::new().on_http(url.parse()?);
     let trace = provider
         .trace_replay_transaction(hash.to_owned(), &[TraceType::Trace])
@@ -50,7 +224,7 @@ pub async fn get_tx_trace(url: &String, hash: &TxHash) -> eyre::Result<TraceResu
     Ok(trace)
 }
 
-pub async fn get_tx_by_pos(url: &String, block: &u64, index: &u64) -> eyre::Result<Transaction> {
+pub async fn get_tx_by_pos(url: &str, block: &u64, index: &u64) -> eyre::Result<Transaction> {
     let provider = ProviderBuilder::new().on_http(url.parse()?);
     let tx = provider
         .raw_request::<(BlockNumberOrTag, Index), Transaction>(
@@ -61,7 +235,7 @@ pub async fn get_tx_by_pos(url: &String, block: &u64, index: &u64) -> eyre::Resu
     Ok(tx)
 }
 
-pub async fn get_tx_receipt(url: &String, hash: &TxHash) -> eyre::Result<TransactionReceipt> {
+pub async fn get_tx_receipt(url: &str, hash: &TxHash) -> eyre::Result<TransactionReceipt> {
     let provider = ProviderBuilder::new().on_http(url.parse()?);
     let receipt = provider
         .get_transaction_receipt(hash.to_owned())
@@ -70,11 +244,58 @@ pub async fn get_tx_receipt(url: &String, hash: &TxHash) -> eyre::Result<Transac
     Ok(receipt)
 }
 
+pub async fn get_logs(url: &str, filter: &Filter) -> eyre::Result<Vec<Log>> {
+    let provider = ProviderBuilder::new().on_http(url.parse()?);
+    let logs = provider.get_logs(filter).await?;
+    Ok(logs)
+}
+
+pub async fn get_block_number(url: &str) -> eyre::Result<u64> {
+    let provider = ProviderBuilder::new().on_http(url.parse()?);
+    let number = provider.get_block_number().await?;
+    Ok(number)
+}
+
+/// First block at which `address` has code, found by bisecting `eth_getCode`
+/// up to `latest`. Needs an archive node.
+pub async fn get_deployment_block(url: &str, address: &Address, latest: u64) -> eyre::Result<u64> {
+    let provider = ProviderBuilder::new().on_http(url.parse()?);
+    let has_code = |block: u64| {
+        let provider = &provider;
+        async move {
+            let code = provider.get_code_at(*address).block_id(block.into()).await?;
+            eyre::Ok(!code.is_empty())
+        }
+    };
+    if !has_code(latest).await? {
+        return Err(eyre!("no code at {}", address));
+    }
+    let (mut low, mut high) = (0, latest);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match has_code(mid).await? {
+            true => high = mid,
+            false => low = mid + 1,
+        }
+    }
+    Ok(low)
+}
+
 pub trait Decoder {
     fn name(&self) -> String;
     fn supported_address(&self) -> Vec<Address>;
     fn supported_selectors(&self) -> Vec<[u8; 4]>;
-    fn decode(&self, context: &DecoderContext) -> eyre::Result<Swap>;
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError>;
+
+    /// Calls that belong to no single swap, for decoders of batches.
+    fn interactions(&self, context: &DecoderContext) -> Result<Vec<Interaction>, DecodeError> {
+        Ok(vec![])
+    }
+
+    /// NFTs bought by the transaction, for routers that also trade NFTs.
+    fn nft_purchases(&self, context: &DecoderContext) -> Result<Vec<NftPurchase>, DecodeError> {
+        Ok(vec![])
+    }
 }
 
 pub enum TxPos {
@@ -83,99 +304,140 @@ pub enum TxPos {
 }
 
 pub struct DecoderContext {
-    rt: tokio::runtime::Runtime,
+    rt: Rc<tokio::runtime::Runtime>,
     rpc_url: String,
 
+    /// the call being decoded, see [`DecoderContext::with_call`]
     tx: Transaction,
-    receipt: OnceCell<TransactionReceipt>,
-    trace: OnceCell<TraceResults>,
+    receipt: Rc<OnceCell<TransactionReceipt>>,
+    trace: Rc<OnceCell<TraceResults>>,
+    balance_deltas: Rc<OnceCell<BalanceDeltas>>,
 }
 
 impl DecoderContext {
-    pub fn decode(rpc_url: String, pos: TxPos) -> eyre::Result<()> {
+    pub fn new(rpc_url: String, pos: TxPos) -> Result<Self, DecodeError> {
         let rt = tokio::runtime::Builder::new_current_thread()
             .enable_all()
-            .build()?;
+            .build()
+            .map_err(|err| DecodeError::rpc(err.into()))?;
 
-        let decoders: Vec<Box<dyn Decoder>> = vec![Box::new(DecoderUnivesalRouter::new())];
         let tx = match pos {
-            TxPos::Hash(hash) => rt.block_on(get_tx(&rpc_url, &hash))?,
-            TxPos::Pos(block, index) => {
-                rt.block_on(get_tx_by_pos(&rpc_url, &block, &index))?
-            }
-        };
-        // let (receipt, trace) = self.rt.block_on(async {
-        //     tokio::join!(
-        //         get_tx_receipt(&self.rpc_url, &tx.hash).into_future(),
-        //         get_tx_trace(&self.rpc_url, &tx.hash).into_future(),
-        //     )
-        // });
-        // self.receipt.replace(receipt?);
-        // self.trace.replace(trace?);
-
-        let to_addr = &tx
-            .to()
-            .ok_or(eyre!("creation transaction is not supported"))?;
-        let selector = extract_selector(&tx)?;
-
-        let context = DecoderContext {
-            rt,
+            TxPos::Hash(hash) => rt.block_on(get_tx(&rpc_url, &hash)),
+            TxPos::Pos(block, index) => rt.block_on(get_tx_by_pos(&rpc_url, &block, &index)),
+        }
+        .map_err(DecodeError::rpc)?;
+        Ok(DecoderContext {
+            rt: Rc::new(rt),
             tx,
             rpc_url,
 
-            receipt: OnceCell::new(),
-            trace: OnceCell::new(),
-        };
-        for decoder in decoders {
-            if !decoder.supported_address().contains(&to_addr) {
-                continue;
-            }
-            if !decoder.supported_selectors().contains(&selector) {
-                continue;
-            }
-            let swap_info = decoder.decode(&context)?;
-            // match decoder.name() {
-                // universal_router::consts::NAME => {}
-                // _ => unreachable!("decoder is not present"),
-            // }
-        }
-        Ok(())
+            receipt: Rc::default(),
+            trace: Rc::default(),
+            balance_deltas: Rc::default(),
+        })
     }
 
     pub fn tx(&self) -> &Transaction {
         &self.tx
     }
 
-    pub fn trace(&self) -> eyre::Result<&TraceResults> {
+    /// A context whose `tx()` is a call made by the transaction, for wrappers
+    /// that hand their inner calldata to another decoder. Receipt, trace and
+    /// balance changes are still the whole transaction's.
+    pub fn with_call(&self, from: Address, to: Address, input: Bytes, value: U256) -> Self {
+        let mut tx = self.tx.clone();
+        tx.from = from;
+        tx.to = Some(to);
+        tx.input = input;
+        tx.value = value;
+        DecoderContext {
+            rt: self.rt.clone(),
+            tx,
+            rpc_url: self.rpc_url.clone(),
+
+            receipt: self.receipt.clone(),
+            trace: self.trace.clone(),
+            balance_deltas: self.balance_deltas.clone(),
+        }
+    }
+
+    pub fn trace(&self) -> Result<&TraceResults, DecodeError> {
         if self.trace.get().is_none() {
             let trace = self
                 .rt
-                .block_on(get_tx_trace(&self.rpc_url, &self.tx().hash))?;
+                .block_on(get_tx_trace(&self.rpc_url, &self.tx().hash))
+                .map_err(DecodeError::rpc)?;
             self.trace.set(trace).unwrap();
         }
         Ok(self.trace.get().unwrap())
     }
 
-    pub fn receipt(&self) -> eyre::Result<&TransactionReceipt> {
+    /// Return data of the call being decoded, from the first successful frame
+    /// of the trace that made it.
+    pub fn output(&self) -> Result<&Bytes, DecodeError> {
+        let tx = self.tx();
+        self.trace()?
+            .trace
+            .iter()
+            .filter(|trace| trace.error.is_none())
+            .find_map(|trace| match (&trace.action, &trace.result) {
+                (Action::Call(call), Some(TraceOutput::Call(output)))
+                    if tx.to == Some(call.to) && call.input == tx.input =>
+                {
+                    Some(&output.output)
+                }
+                _ => None,
+            })
+            .ok_or(DecodeError::MissingOutput(tx.to.unwrap_or_default()))
+    }
+
+    pub fn receipt(&self) -> Result<&TransactionReceipt, DecodeError> {
         if self.receipt.get().is_none() {
             let receipt = self
                 .rt
-                .block_on(get_tx_receipt(&self.rpc_url, &self.tx().hash))?;
+                .block_on(get_tx_receipt(&self.rpc_url, &self.tx().hash))
+                .map_err(DecodeError::rpc)?;
             self.receipt.set(receipt).unwrap();
         }
         Ok(self.receipt.get().unwrap())
     }
 
-    pub fn logs(&self) -> eyre::Result<&[Log<LogData>]> {
+    pub fn logs(&self) -> Result<&[Log<LogData>], DecodeError> {
         Ok(self.receipt()?.inner.logs())
     }
+
+    /// Logs matching `filter` anywhere on chain, not only in this transaction.
+    pub fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, DecodeError> {
+        self.rt
+            .block_on(get_logs(&self.rpc_url, filter))
+            .map_err(DecodeError::rpc)
+    }
+
+    pub fn block_number(&self) -> Result<u64, DecodeError> {
+        self.rt
+            .block_on(get_block_number(&self.rpc_url))
+            .map_err(DecodeError::rpc)
+    }
+
+    /// Block `address` was deployed in, at or before `latest`.
+    pub fn deployment_block(&self, address: &Address, latest: u64) -> Result<u64, DecodeError> {
+        self.rt
+            .block_on(get_deployment_block(&self.rpc_url, address, latest))
+            .map_err(DecodeError::rpc)
+    }
+
+    pub fn balance_deltas(&self) -> Result<&BalanceDeltas, DecodeError> {
+        if self.balance_deltas.get().is_none() {
+            let deltas = BalanceDeltas::new(self.logs()?, &self.trace()?.trace);
+            self.balance_deltas.set(deltas).unwrap();
+        }
+        Ok(self.balance_deltas.get().unwrap())
+    }
 }
 
-fn extract_selector(tx: &Transaction) -> eyre::Result<[u8; 4]> {
-    let mut selector = [0 as u8; 4];
-    match tx.input.take(4).read(&mut selector)? {
-        4 => {}
-        _ => return Err(eyre!("insufficient calldata")),
+pub(crate) fn extract_selector(input: &[u8]) -> Result<[u8; 4], DecodeError> {
+    match input.get(..4) {
+        Some(selector) => Ok(selector.try_into().unwrap()),
+        None => Err(DecodeError::CalldataTooShort),
     }
-    Ok(selector)
 }
@@ -1,4 +1,23 @@
-use alloy::{rpc::types::Transaction, sol, sol_types::SolInterface};
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::network::TransactionResponse,
+    rpc::types::Transaction,
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface},
+};
+
+use super::{
+    balance::decode_event,
+    oneinch_v5::{uniswap_v3_decode_swap, unoswap_decode_swap},
+    DecodeError, Decoder, DecoderContext, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "1inch AggregationRouterV4";
+    pub const ROUTER: Address = address!("1111111254fb6c44bAC0beD2854e76F90643097d");
+}
 
 sol! {
 
@@ -112,26 +131,265 @@ interface AggregationRouterV4 {
 
 }
 
-// pub fn decode_swap(tx: Transaction) -> eyre::Result<()> {
-//     use AggregationRouterV4::AggregationRouterV4Calls as C;
-
-//     match C::abi_decode(&tx.input, true)? {
-//         C::clipperSwap(call) => {
-//             let from_address = tx.from();
-//         }
-//         C::clipperSwapTo(_) => todo!(),
-//         C::clipperSwapToWithPermit(_) => todo!(),
-//         C::fillOrderRFQ(_) => todo!(),
-//         C::fillOrderRFQTo(_) => todo!(),
-//         C::fillOrderRFQToWithPermit(_) => todo!(),
-//         C::swap(_) => todo!(),
-//         C::uniswapV3Swap(_) => todo!(),
-//         C::uniswapV3SwapCallback(_) => todo!(),
-//         C::uniswapV3SwapTo(_) => todo!(),
-//         C::uniswapV3SwapToWithPermit(_) => todo!(),
-//         C::unoswap(_) => todo!(),
-//         C::unoswapWithPermit(_) => todo!(),
-//         _ => {}
-//     }
-//     Ok(())
-// }
+pub struct DecoderOneInchV4 {}
+
+impl DecoderOneInchV4 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderOneInchV4 {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use AggregationRouterV4 as C;
+        vec![
+            C::swapCall::SELECTOR,
+            C::unoswapCall::SELECTOR,
+            C::unoswapWithPermitCall::SELECTOR,
+            C::uniswapV3SwapCall::SELECTOR,
+            C::uniswapV3SwapToCall::SELECTOR,
+            C::uniswapV3SwapToWithPermitCall::SELECTOR,
+            C::clipperSwapCall::SELECTOR,
+            C::clipperSwapToCall::SELECTOR,
+            C::clipperSwapToWithPermitCall::SELECTOR,
+            C::fillOrderRFQCall::SELECTOR,
+            C::fillOrderRFQToCall::SELECTOR,
+            C::fillOrderRFQToWithPermitCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use AggregationRouterV4::AggregationRouterV4Calls as C;
+        let tx = context.tx();
+        let msg_sender = tx.from();
+        let deltas = context.balance_deltas()?;
+
+        let mut legs = vec![];
+        // (recipient, input token, output token, input amount, output amount)
+        let (to_address, input_token, output_token, input_amount, output_amount) =
+            match C::abi_decode(&tx.input, true)? {
+                C::swap(call) => {
+                    let desc = call.desc;
+                    let recipient = match desc.dstReceiver.is_zero() {
+                        true => msg_sender,
+                        false => desc.dstReceiver,
+                    };
+                    let returned =
+                        AggregationRouterV4::swapCall::abi_decode_returns(context.output()?, true)?;
+                    (
+                        recipient,
+                        desc.srcToken,
+                        desc.dstToken,
+                        returned.spentAmount,
+                        returned.returnAmount,
+                    )
+                }
+                C::unoswap(call) => {
+                    let pools = unoswap_pool_words(&call.pools);
+                    let entry =
+                        unoswap_decode_swap(context, &consts::ROUTER, call.srcToken, &pools)?;
+                    legs = entry.legs;
+                    (
+                        msg_sender,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::unoswapWithPermit(call) => {
+                    let pools = unoswap_pool_words(&call.pools);
+                    let entry =
+                        unoswap_decode_swap(context, &consts::ROUTER, call.srcToken, &pools)?;
+                    legs = entry.legs;
+                    (
+                        msg_sender,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3Swap(call) => {
+                    let entry =
+                        uniswap_v3_decode_swap(context, &consts::ROUTER, None, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        msg_sender,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3SwapTo(call) => {
+                    let entry =
+                        uniswap_v3_decode_swap(context, &consts::ROUTER, None, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3SwapToWithPermit(call) => {
+                    let entry = uniswap_v3_decode_swap(
+                        context,
+                        &consts::ROUTER,
+                        Some(call.srcToken),
+                        &call.pools,
+                    )?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                // v4 clipper calls only carry the minimum return, the bought
+                // amount is what the call returned
+                C::clipperSwap(call) => (
+                    msg_sender,
+                    call.srcToken,
+                    call.dstToken,
+                    call.amount,
+                    clipper_return_amount(context)?,
+                ),
+                C::clipperSwapTo(call) => (
+                    call.recipient,
+                    call.srcToken,
+                    call.dstToken,
+                    call.amount,
+                    clipper_return_amount(context)?,
+                ),
+                C::clipperSwapToWithPermit(call) => (
+                    call.recipient,
+                    call.srcToken,
+                    call.dstToken,
+                    call.amount,
+                    clipper_return_amount(context)?,
+                ),
+                C::fillOrderRFQ(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        msg_sender,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQTo(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        call.target,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQToWithPermit(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        call.target,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+            };
+        let mut swap = Swap {
+            legs,
+            ..Swap::new(
+                msg_sender,
+                to_address,
+                input_token,
+                output_token,
+                input_amount,
+                output_amount,
+            )
+        };
+        deltas.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
+
+/// `returnAmount` of a `clipperSwap*` call. The variants share their return
+/// type, so the plain one decodes them all.
+fn clipper_return_amount(context: &DecoderContext) -> Result<U256, DecodeError> {
+    let returned =
+        AggregationRouterV4::clipperSwapCall::abi_decode_returns(context.output()?, true)?;
+    Ok(returned.returnAmount)
+}
+
+/// V4 passes `unoswap` pools as `bytes32`, packed the same way as v5's
+/// `uint256` words.
+fn unoswap_pool_words(pools: &[B256]) -> Vec<U256> {
+    pools
+        .iter()
+        .map(|pool| U256::from_be_bytes(pool.0))
+        .collect()
+}
+
+/// Making and taking amounts of an RFQ fill, as the fill returned them. V4
+/// doesn't return the order hash, so the router's `OrderFilledRFQ` log is
+/// matched on the making amount instead.
+fn rfq_fill_amounts(context: &DecoderContext) -> Result<(U256, U256), DecodeError> {
+    // every `fillOrderRFQ*` variant returns the same tuple
+    let returned =
+        AggregationRouterV4::fillOrderRFQCall::abi_decode_returns(context.output()?, true)?;
+    let (making, taking) = (returned._0, returned._1);
+    context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::ROUTER && !log.removed)
+        .filter_map(decode_event::<AggregationRouterV4::OrderFilledRFQ>)
+        .find(|filled| filled.makingAmount == making)
+        .ok_or(DecodeError::MissingLogs(consts::ROUTER))?;
+    Ok((making, taking))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, b256};
+
+    use super::*;
+    use crate::interfaces::oneinch_v5::UnoswapPool;
+
+    const USDC_WETH_V2: Address = address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+
+    #[test]
+    fn unoswap_pool_word() {
+        let pools = unoswap_pool_words(&[
+            // WETH to USDC through the 0.3% pair
+            b256!("80000000000000003b6d0340b4e16d0168e52d35cacd2c6185b44281ec28c9dc"),
+            // USDC to ETH, unwrapped after the pair
+            b256!("40000000000000003b6d0340b4e16d0168e52d35cacd2c6185b44281ec28c9dc"),
+        ]);
+        let pool = UnoswapPool::from(pools[0]);
+        assert_eq!(pool.pair, USDC_WETH_V2);
+        assert!(pool.reverse);
+        assert!(!pool.unwrap_weth);
+        assert_eq!(pool.numerator, 997_000_000);
+
+        let pool = UnoswapPool::from(pools[1]);
+        assert_eq!(pool.pair, USDC_WETH_V2);
+        assert!(!pool.reverse);
+        assert!(pool.unwrap_weth);
+    }
+}
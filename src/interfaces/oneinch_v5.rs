@@ -1,4 +1,29 @@
-use alloy::{rpc::types::Transaction, sol, sol_types::SolInterface};
+use alloy::{
+    primitives::{Address, LogData, B256, I256, U256},
+    providers::network::TransactionResponse,
+    rpc::types::{
+        trace::parity::{Action, TransactionTrace},
+        Log, Transaction,
+    },
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface},
+};
+
+use super::{
+    balance::{self, decode_event, is_eth},
+    universal_router::{
+        pool_token_in, pool_token_out, v2_decode_leg, v3_decode_leg, v3_leg, v3_pool_fee, Pool,
+        SwapEntry,
+    },
+    DecodeError, Decoder, DecoderContext, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "1inch AggregationRouterV5";
+    pub const ROUTER: Address = address!("1111111254EEB25477B68fb85Ed929f73A960582");
+}
 
 sol! {
 
@@ -242,31 +267,514 @@ interface AggregationRouterV5 {
 
 }
 
-// clipperSwap*
-// fillOrder*
-pub fn decode_swap(tx: Transaction) -> eyre::Result<()> {
-    use AggregationRouterV5::AggregationRouterV5Calls as C;
-
-    match C::abi_decode(&tx.input, true)? {
-        C::clipperSwap(_) => todo!(),
-        C::clipperSwapTo(_) => todo!(),
-        C::clipperSwapToWithPermit(_) => todo!(),
-        C::fillOrder(_) => todo!(),
-        C::fillOrderRFQ(_) => todo!(),
-        C::fillOrderRFQCompact(_) => todo!(),
-        C::fillOrderRFQTo(_) => todo!(),
-        C::fillOrderRFQToWithPermit(_) => todo!(),
-        C::fillOrderTo(_) => todo!(),
-        C::fillOrderToWithPermit(_) => todo!(),
-        C::swap(_) => todo!(),
-        C::uniswapV3Swap(_) => todo!(),
-        C::uniswapV3SwapCallback(_) => todo!(),
-        C::uniswapV3SwapTo(_) => todo!(),
-        C::uniswapV3SwapToWithPermit(_) => todo!(),
-        C::unoswap(_) => todo!(),
-        C::unoswapTo(_) => todo!(),
-        C::unoswapToWithPermit(_) => todo!(),
-        _ => {}
+pub struct DecoderOneInchV5 {}
+
+impl DecoderOneInchV5 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderOneInchV5 {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
     }
-    Ok(())
-}
\ No newline at end of file
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use AggregationRouterV5 as C;
+        vec![
+            C::swapCall::SELECTOR,
+            C::unoswapCall::SELECTOR,
+            C::unoswapToCall::SELECTOR,
+            C::unoswapToWithPermitCall::SELECTOR,
+            C::uniswapV3SwapCall::SELECTOR,
+            C::uniswapV3SwapToCall::SELECTOR,
+            C::uniswapV3SwapToWithPermitCall::SELECTOR,
+            C::clipperSwapCall::SELECTOR,
+            C::clipperSwapToCall::SELECTOR,
+            C::clipperSwapToWithPermitCall::SELECTOR,
+            C::fillOrderCall::SELECTOR,
+            C::fillOrderToCall::SELECTOR,
+            C::fillOrderToWithPermitCall::SELECTOR,
+            C::fillOrderRFQCall::SELECTOR,
+            C::fillOrderRFQCompactCall::SELECTOR,
+            C::fillOrderRFQToCall::SELECTOR,
+            C::fillOrderRFQToWithPermitCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use AggregationRouterV5::AggregationRouterV5Calls as C;
+        let tx = context.tx();
+        let msg_sender = tx.from();
+        let deltas = context.balance_deltas()?;
+
+        let mut legs = vec![];
+        // (recipient, input token, output token, input amount, output amount)
+        let (to_address, input_token, output_token, input_amount, output_amount) =
+            match C::abi_decode(&tx.input, true)? {
+                C::swap(call) => {
+                    let desc = call.desc;
+                    let recipient = match desc.dstReceiver.is_zero() {
+                        true => msg_sender,
+                        false => desc.dstReceiver,
+                    };
+                    let returned =
+                        AggregationRouterV5::swapCall::abi_decode_returns(context.output()?, true)?;
+                    (
+                        recipient,
+                        desc.srcToken,
+                        desc.dstToken,
+                        returned.spentAmount,
+                        returned.returnAmount,
+                    )
+                }
+                C::unoswap(call) => {
+                    let entry =
+                        unoswap_decode_swap(context, &consts::ROUTER, call.srcToken, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        msg_sender,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::unoswapTo(call) => {
+                    let entry =
+                        unoswap_decode_swap(context, &consts::ROUTER, call.srcToken, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::unoswapToWithPermit(call) => {
+                    let entry =
+                        unoswap_decode_swap(context, &consts::ROUTER, call.srcToken, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3Swap(call) => {
+                    let entry =
+                        uniswap_v3_decode_swap(context, &consts::ROUTER, None, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        msg_sender,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3SwapTo(call) => {
+                    let entry =
+                        uniswap_v3_decode_swap(context, &consts::ROUTER, None, &call.pools)?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::uniswapV3SwapToWithPermit(call) => {
+                    let entry = uniswap_v3_decode_swap(
+                        context,
+                        &consts::ROUTER,
+                        Some(call.srcToken),
+                        &call.pools,
+                    )?;
+                    legs = entry.legs;
+                    (
+                        call.recipient,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+                C::clipperSwap(call) => (
+                    msg_sender,
+                    call.srcToken,
+                    call.dstToken,
+                    call.inputAmount,
+                    clipper_return_amount(context)?,
+                ),
+                C::clipperSwapTo(call) => (
+                    call.recipient,
+                    call.srcToken,
+                    call.dstToken,
+                    call.inputAmount,
+                    clipper_return_amount(context)?,
+                ),
+                C::clipperSwapToWithPermit(call) => (
+                    call.recipient,
+                    call.srcToken,
+                    call.dstToken,
+                    call.inputAmount,
+                    clipper_return_amount(context)?,
+                ),
+                // the taker pays the taker asset and gets the maker asset; either
+                // amount may be left to the order's getters, so both come from
+                // the fill itself
+                C::fillOrder(call) => {
+                    let order = call.order;
+                    let (making, taking) = order_fill_amounts(context)?;
+                    (
+                        msg_sender,
+                        order.takerAsset,
+                        order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderTo(call) => {
+                    let order = call.order_;
+                    let (making, taking) = order_fill_amounts(context)?;
+                    (
+                        call.target,
+                        order.takerAsset,
+                        order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderToWithPermit(call) => {
+                    let order = call.order;
+                    let (making, taking) = order_fill_amounts(context)?;
+                    (
+                        call.target,
+                        order.takerAsset,
+                        order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQ(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        msg_sender,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQCompact(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        msg_sender,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQTo(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        call.target,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                C::fillOrderRFQToWithPermit(call) => {
+                    let (making, taking) = rfq_fill_amounts(context)?;
+                    (
+                        call.target,
+                        call.order.takerAsset,
+                        call.order.makerAsset,
+                        taking,
+                        making,
+                    )
+                }
+                call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+            };
+        let mut swap = Swap {
+            legs,
+            ..Swap::new(
+                msg_sender,
+                to_address,
+                input_token,
+                output_token,
+                input_amount,
+                output_amount,
+            )
+        };
+        deltas.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
+
+/// `returnAmount` of a `clipperSwap*` call. The variants share their return
+/// type, so the plain one decodes them all.
+fn clipper_return_amount(context: &DecoderContext) -> Result<U256, DecodeError> {
+    let returned =
+        AggregationRouterV5::clipperSwapCall::abi_decode_returns(context.output()?, true)?;
+    Ok(returned.returnAmount)
+}
+
+/// Making and taking amounts of a limit order fill. `OrderFilled` only
+/// reports what is left of the order, so the amounts are the ones the fill
+/// returned, for the order hash the router logged it under.
+fn order_fill_amounts(context: &DecoderContext) -> Result<(U256, U256), DecodeError> {
+    // `fillOrderTo*` return the same tuple as `fillOrder`
+    let returned = AggregationRouterV5::fillOrderCall::abi_decode_returns(context.output()?, true)?;
+    let (making, taking, order_hash) = (returned._0, returned._1, returned._2);
+    context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::ROUTER && !log.removed)
+        .filter_map(decode_event::<AggregationRouterV5::OrderFilled>)
+        .find(|filled| filled.orderHash == order_hash)
+        .ok_or(DecodeError::MissingLogs(consts::ROUTER))?;
+    Ok((making, taking))
+}
+
+/// Making and taking amounts of an RFQ fill, as the fill returned them,
+/// confirmed by the router's `OrderFilledRFQ` log for the same order hash.
+fn rfq_fill_amounts(context: &DecoderContext) -> Result<(U256, U256), DecodeError> {
+    // every `fillOrderRFQ*` variant returns the same tuple
+    let returned =
+        AggregationRouterV5::fillOrderRFQCall::abi_decode_returns(context.output()?, true)?;
+    let (making, taking, order_hash) = (returned._0, returned._1, returned._2);
+    context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::ROUTER && !log.removed)
+        .filter_map(decode_event::<AggregationRouterV5::OrderFilledRFQ>)
+        .find(|filled| filled.orderHash == order_hash)
+        .ok_or(DecodeError::MissingLogs(consts::ROUTER))?;
+    Ok((making, taking))
+}
+
+/// A packed `unoswap` pool word: the pair address in the low 160 bits, the
+/// pair's fee numerator (out of 1e9) above it and `UnoswapRouter`'s flags in
+/// the top bits.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UnoswapPool {
+    pub(crate) pair: Address,
+    /// token1 is swapped for token0
+    pub(crate) reverse: bool,
+    /// WETH received from the last pair is unwrapped to ETH
+    pub(crate) unwrap_weth: bool,
+    pub(crate) numerator: u32,
+}
+
+impl From<U256> for UnoswapPool {
+    fn from(word: U256) -> Self {
+        Self {
+            pair: Address::from_word(B256::from(word)),
+            reverse: word.bit(255),
+            unwrap_weth: word.bit(254),
+            numerator: ((word >> 160usize) & U256::from(u32::MAX)).to(),
+        }
+    }
+}
+
+/// Decodes the V2-style hops of an `unoswap` call. Pool words don't carry
+/// token addresses, so each hop's output token is the one its pair
+/// transferred out within its swap call, and the input of the first hop is
+/// `src_token`.
+pub(crate) fn unoswap_decode_swap(
+    context: &DecoderContext,
+    router: &Address,
+    src_token: Address,
+    pools: &[U256],
+) -> Result<SwapEntry, DecodeError> {
+    let logs = context.logs()?;
+    let trace = &context.trace()?.trace;
+    let pools = pools
+        .iter()
+        .map(|word| UnoswapPool::from(*word))
+        .collect::<Vec<_>>();
+    let mut token_in = match is_eth(&src_token) {
+        true => balance::consts::WETH,
+        false => src_token,
+    };
+    let legs = pools
+        .iter()
+        .enumerate()
+        .map(|(index, hop)| {
+            let visit = pools[..index].iter().filter(|p| p.pair == hop.pair).count();
+            let token_out = pool_token_out(trace, &hop.pair, visit)?;
+            let pool = Pool {
+                token_in,
+                token_out,
+                fee: 1_000_000_000u32.saturating_sub(hop.numerator) / 1000,
+                pool: hop.pair,
+                reverse: hop.reverse,
+            };
+            token_in = token_out;
+            v2_decode_leg(router, &pool, logs)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut entry = SwapEntry::from_legs(legs)?;
+    if pools.last().is_some_and(|hop| hop.unwrap_weth) {
+        entry.token_out = balance::consts::ETH;
+    }
+    Ok(entry)
+}
+
+/// A packed `uniswapV3Swap` pool word: the pool address in the low 160 bits
+/// and `UnoswapV3Router`'s flags in the top bits.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UniswapV3Pool {
+    pub(crate) pool: Address,
+    pub(crate) zero_for_one: bool,
+    /// WETH received from the last pool is unwrapped to ETH
+    pub(crate) unwrap_weth: bool,
+}
+
+impl From<U256> for UniswapV3Pool {
+    fn from(word: U256) -> Self {
+        Self {
+            pool: Address::from_word(B256::from(word)),
+            zero_for_one: !word.bit(255),
+            unwrap_weth: word.bit(253),
+        }
+    }
+}
+
+/// Decodes the hops of a `uniswapV3Swap` call.
+///
+/// The first hop's input is `src_token` when the call carries it, otherwise
+/// the token paid into the first pool within its swap call; every other
+/// token is the one a pool transferred out within its swap call. Amounts
+/// come from the pools' `Swap` events. A pool that appears more than once in
+/// the transaction has one event per visit, so those hops are matched, in
+/// order, to the `uniswapV3SwapCallback` calls the pool made back into the
+/// router instead.
+pub(crate) fn uniswap_v3_decode_swap(
+    context: &DecoderContext,
+    router: &Address,
+    src_token: Option<Address>,
+    pools: &[U256],
+) -> Result<SwapEntry, DecodeError> {
+    let logs = context.logs()?;
+    let trace = &context.trace()?.trace;
+    let pools = pools
+        .iter()
+        .map(|word| UniswapV3Pool::from(*word))
+        .collect::<Vec<_>>();
+    let first = pools.first().ok_or(DecodeError::EmptyPath)?;
+    let mut token_in = match src_token {
+        Some(token) if is_eth(&token) => balance::consts::WETH,
+        Some(token) => token,
+        None => pool_token_in(trace, &first.pool, 0)?,
+    };
+    let mut legs = vec![];
+    for (index, hop) in pools.iter().enumerate() {
+        let visit = pools[..index].iter().filter(|p| p.pool == hop.pool).count();
+        let token_out = pool_token_out(trace, &hop.pool, visit)?;
+        let pool = Pool {
+            token_in,
+            token_out,
+            fee: v3_pool_fee(token_in, token_out, &hop.pool),
+            pool: hop.pool,
+            reverse: !hop.zero_for_one,
+        };
+        let leg = match v3_decode_leg(router, &pool, logs) {
+            Err(DecodeError::AmbiguousPoolLogs(_)) => {
+                let (amount_0, amount_1) = v3_callback_deltas(trace, router, &hop.pool)
+                    .nth(visit)
+                    .ok_or(DecodeError::AmbiguousPoolLogs(hop.pool))?;
+                v3_leg(&pool, amount_0, amount_1)
+            }
+            leg => leg?,
+        };
+        legs.push(leg);
+        token_in = token_out;
+    }
+
+    let mut entry = SwapEntry::from_legs(legs)?;
+    if pools.last().is_some_and(|hop| hop.unwrap_weth) {
+        entry.token_out = balance::consts::ETH;
+    }
+    Ok(entry)
+}
+
+/// Deltas passed by `pool` to `router`'s `uniswapV3SwapCallback`, in call
+/// order.
+fn v3_callback_deltas<'a>(
+    trace: &'a [TransactionTrace],
+    router: &'a Address,
+    pool: &'a Address,
+) -> impl Iterator<Item = (I256, I256)> + 'a {
+    use AggregationRouterV5::uniswapV3SwapCallbackCall as Callback;
+    trace
+        .iter()
+        .filter(|trace| trace.error.is_none())
+        .filter_map(move |trace| match &trace.action {
+            Action::Call(call) if call.from == *pool && call.to == *router => {
+                let callback = Callback::abi_decode(&call.input, true).ok()?;
+                Some((callback.amount0Delta, callback.amount1Delta))
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, uint};
+
+    use super::*;
+
+    const USDC_WETH_V2: Address = address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+    const USDC_WETH_V3: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+
+    #[test]
+    fn unoswap_pool_word() {
+        // WETH to USDC through the 0.3% pair
+        let pool = UnoswapPool::from(uint!(
+            0x80000000000000003b6d0340b4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256
+        ));
+        assert_eq!(pool.pair, USDC_WETH_V2);
+        assert!(pool.reverse);
+        assert!(!pool.unwrap_weth);
+        assert_eq!(pool.numerator, 997_000_000);
+
+        // USDC to ETH, unwrapped after the pair
+        let pool = UnoswapPool::from(uint!(
+            0x40000000000000003b6d0340b4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256
+        ));
+        assert_eq!(pool.pair, USDC_WETH_V2);
+        assert!(!pool.reverse);
+        assert!(pool.unwrap_weth);
+    }
+
+    #[test]
+    fn uniswap_v3_pool_word() {
+        let pool = UniswapV3Pool::from(uint!(
+            0x80000000000000000000000088e6a0c2ddd26feeb64f039a2c41296fcb3f5640_U256
+        ));
+        assert_eq!(pool.pool, USDC_WETH_V3);
+        assert!(!pool.zero_for_one);
+        assert!(!pool.unwrap_weth);
+
+        let pool = UniswapV3Pool::from(uint!(
+            0x20000000000000000000000088e6a0c2ddd26feeb64f039a2c41296fcb3f5640_U256
+        ));
+        assert!(pool.zero_for_one);
+        assert!(pool.unwrap_weth);
+    }
+}
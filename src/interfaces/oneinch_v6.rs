@@ -0,0 +1,597 @@
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::network::TransactionResponse,
+    rpc::types::trace::parity::{Action, TransactionTrace},
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface},
+};
+
+use super::{
+    balance::{self, decode_event, is_eth},
+    universal_router::{
+        frame_calls, pool_frame, pool_token_out, v2_decode_leg, v3_decode_leg, v3_pool_fee, Pool,
+        SwapEntry,
+    },
+    DecodeError, Decoder, DecoderContext, Protocol, Swap, SwapLeg,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "1inch AggregationRouterV6";
+    pub const ROUTER: Address = address!("111111125421cA6dc452d289314280a0f8842A65");
+
+    // ProtocolLib
+    pub const PROTOCOL_OFFSET: usize = 253;
+    pub const WETH_UNWRAP_FLAG: usize = 252;
+    pub const WETH_NOT_WRAP_FLAG: usize = 251;
+
+    // UnoswapRouter
+    pub const ZERO_FOR_ONE_FLAG: usize = 247;
+    pub const UNISWAP_V2_NUMERATOR_OFFSET: usize = 160;
+    pub const CURVE_FROM_TOKEN_OFFSET: usize = 224;
+    pub const CURVE_TO_TOKEN_OFFSET: usize = 232;
+
+    // TakerTraitsLib
+    pub const ARGS_HAS_TARGET: usize = 251;
+}
+
+sol! {
+
+// cast interface 0x111111125421ca6dc452d289314280a0f8842a65
+interface AggregationRouterV6 {
+    type Address is uint256;
+    type MakerTraits is uint256;
+    type TakerTraits is uint256;
+
+    struct Order {
+        uint256 salt;
+        Address maker;
+        Address receiver;
+        Address makerAsset;
+        Address takerAsset;
+        uint256 makingAmount;
+        uint256 takingAmount;
+        MakerTraits makerTraits;
+    }
+
+    struct SwapDescription {
+        address srcToken;
+        address dstToken;
+        address payable srcReceiver;
+        address payable dstReceiver;
+        uint256 amount;
+        uint256 minReturnAmount;
+        uint256 flags;
+    }
+
+    event OrderFilled(bytes32 orderHash, uint256 remainingAmount);
+
+    function clipperSwap(
+        address clipperExchange,
+        Address srcToken,
+        address dstToken,
+        uint256 inputAmount,
+        uint256 outputAmount,
+        uint256 goodUntil,
+        bytes32 r,
+        bytes32 vs
+    ) external payable returns (uint256 returnAmount);
+    function clipperSwapTo(
+        address clipperExchange,
+        address payable recipient,
+        Address srcToken,
+        address dstToken,
+        uint256 inputAmount,
+        uint256 outputAmount,
+        uint256 goodUntil,
+        bytes32 r,
+        bytes32 vs
+    ) external payable returns (uint256 returnAmount);
+    function ethUnoswap(TakerTraits minReturn, Address dex) external payable returns (uint256 returnAmount);
+    function ethUnoswap2(TakerTraits minReturn, Address dex, Address dex2)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function ethUnoswap3(TakerTraits minReturn, Address dex, Address dex2, Address dex3)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function ethUnoswapTo(Address to, TakerTraits minReturn, Address dex)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function ethUnoswapTo2(Address to, TakerTraits minReturn, Address dex, Address dex2)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function ethUnoswapTo3(Address to, TakerTraits minReturn, Address dex, Address dex2, Address dex3)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function fillContractOrder(Order memory order, bytes memory signature, uint256 amount, TakerTraits takerTraits)
+        external
+        returns (uint256, uint256, bytes32);
+    function fillContractOrderArgs(
+        Order memory order,
+        bytes memory signature,
+        uint256 amount,
+        TakerTraits takerTraits,
+        bytes memory args
+    ) external returns (uint256, uint256, bytes32);
+    function fillOrder(Order memory order, bytes32 r, bytes32 vs, uint256 amount, TakerTraits takerTraits)
+        external
+        payable
+        returns (uint256, uint256, bytes32);
+    function fillOrderArgs(
+        Order memory order,
+        bytes32 r,
+        bytes32 vs,
+        uint256 amount,
+        TakerTraits takerTraits,
+        bytes memory args
+    ) external payable returns (uint256, uint256, bytes32);
+    function swap(address executor, SwapDescription memory desc, bytes memory data)
+        external
+        payable
+        returns (uint256 returnAmount, uint256 spentAmount);
+    function unoswap(Address token, uint256 amount, uint256 minReturn, Address dex)
+        external
+        returns (uint256 returnAmount);
+    function unoswap2(Address token, uint256 amount, uint256 minReturn, Address dex, Address dex2)
+        external
+        returns (uint256 returnAmount);
+    function unoswap3(Address token, uint256 amount, uint256 minReturn, Address dex, Address dex2, Address dex3)
+        external
+        returns (uint256 returnAmount);
+    function unoswapTo(Address to, Address token, uint256 amount, uint256 minReturn, Address dex)
+        external
+        returns (uint256 returnAmount);
+    function unoswapTo2(Address to, Address token, uint256 amount, uint256 minReturn, Address dex, Address dex2)
+        external
+        returns (uint256 returnAmount);
+    function unoswapTo3(
+        Address to,
+        Address token,
+        uint256 amount,
+        uint256 minReturn,
+        Address dex,
+        Address dex2,
+        Address dex3
+    ) external returns (uint256 returnAmount);
+}
+
+}
+
+pub struct DecoderOneInchV6 {}
+
+impl DecoderOneInchV6 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderOneInchV6 {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use AggregationRouterV6 as C;
+        vec![
+            C::swapCall::SELECTOR,
+            C::unoswapCall::SELECTOR,
+            C::unoswapToCall::SELECTOR,
+            C::unoswap2Call::SELECTOR,
+            C::unoswapTo2Call::SELECTOR,
+            C::unoswap3Call::SELECTOR,
+            C::unoswapTo3Call::SELECTOR,
+            C::ethUnoswapCall::SELECTOR,
+            C::ethUnoswapToCall::SELECTOR,
+            C::ethUnoswap2Call::SELECTOR,
+            C::ethUnoswapTo2Call::SELECTOR,
+            C::ethUnoswap3Call::SELECTOR,
+            C::ethUnoswapTo3Call::SELECTOR,
+            C::clipperSwapCall::SELECTOR,
+            C::clipperSwapToCall::SELECTOR,
+            C::fillOrderCall::SELECTOR,
+            C::fillOrderArgsCall::SELECTOR,
+            C::fillContractOrderCall::SELECTOR,
+            C::fillContractOrderArgsCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use AggregationRouterV6::AggregationRouterV6Calls as C;
+        let tx = context.tx();
+        let msg_sender = tx.from();
+        let deltas = context.balance_deltas()?;
+
+        let swap = |to_address, input_token, output_token, input_amount, output_amount| {
+            Swap::new(
+                msg_sender,
+                to_address,
+                input_token,
+                output_token,
+                input_amount,
+                output_amount,
+            )
+        };
+        let unoswap = |to: U256, token: Address, amount: U256, dexes: &[U256]| {
+            let entry = unoswap_decode_swap(context, token, dexes)?;
+            Ok::<_, DecodeError>(Swap {
+                legs: entry.legs,
+                ..Swap::new(
+                    msg_sender,
+                    word_address(to),
+                    token,
+                    entry.token_out,
+                    amount,
+                    entry.amount_out,
+                )
+            })
+        };
+        let sender = U256::from_be_slice(msg_sender.as_slice());
+        let eth = balance::consts::ETH;
+
+        let mut swap = match C::abi_decode(&tx.input, true)? {
+            C::swap(call) => {
+                let desc = call.desc;
+                let recipient = match desc.dstReceiver.is_zero() {
+                    true => msg_sender,
+                    false => desc.dstReceiver,
+                };
+                let returned =
+                    AggregationRouterV6::swapCall::abi_decode_returns(context.output()?, true)?;
+                swap(
+                    recipient,
+                    desc.srcToken,
+                    desc.dstToken,
+                    returned.spentAmount,
+                    returned.returnAmount,
+                )
+            }
+            C::unoswap(call) => {
+                unoswap(sender, word_address(call.token), call.amount, &[call.dex])?
+            }
+            C::unoswapTo(call) => {
+                unoswap(call.to, word_address(call.token), call.amount, &[call.dex])?
+            }
+            C::unoswap2(call) => unoswap(
+                sender,
+                word_address(call.token),
+                call.amount,
+                &[call.dex, call.dex2],
+            )?,
+            C::unoswapTo2(call) => unoswap(
+                call.to,
+                word_address(call.token),
+                call.amount,
+                &[call.dex, call.dex2],
+            )?,
+            C::unoswap3(call) => unoswap(
+                sender,
+                word_address(call.token),
+                call.amount,
+                &[call.dex, call.dex2, call.dex3],
+            )?,
+            C::unoswapTo3(call) => unoswap(
+                call.to,
+                word_address(call.token),
+                call.amount,
+                &[call.dex, call.dex2, call.dex3],
+            )?,
+            C::ethUnoswap(call) => unoswap(sender, eth, tx.value(), &[call.dex])?,
+            C::ethUnoswapTo(call) => unoswap(call.to, eth, tx.value(), &[call.dex])?,
+            C::ethUnoswap2(call) => unoswap(sender, eth, tx.value(), &[call.dex, call.dex2])?,
+            C::ethUnoswapTo2(call) => unoswap(call.to, eth, tx.value(), &[call.dex, call.dex2])?,
+            C::ethUnoswap3(call) => {
+                unoswap(sender, eth, tx.value(), &[call.dex, call.dex2, call.dex3])?
+            }
+            C::ethUnoswapTo3(call) => {
+                unoswap(call.to, eth, tx.value(), &[call.dex, call.dex2, call.dex3])?
+            }
+            C::clipperSwap(call) => swap(
+                msg_sender,
+                word_address(call.srcToken),
+                call.dstToken,
+                call.inputAmount,
+                clipper_return_amount(context)?,
+            ),
+            C::clipperSwapTo(call) => swap(
+                call.recipient,
+                word_address(call.srcToken),
+                call.dstToken,
+                call.inputAmount,
+                clipper_return_amount(context)?,
+            ),
+            // limit orders: the taker pays the taker asset and gets the maker
+            // asset
+            C::fillOrder(call) => fill_order(context, &swap, &call.order, call.takerTraits, &[])?,
+            C::fillOrderArgs(call) => {
+                fill_order(context, &swap, &call.order, call.takerTraits, &call.args)?
+            }
+            C::fillContractOrder(call) => {
+                fill_order(context, &swap, &call.order, call.takerTraits, &[])?
+            }
+            C::fillContractOrderArgs(call) => {
+                fill_order(context, &swap, &call.order, call.takerTraits, &call.args)?
+            }
+        };
+        deltas.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
+
+fn fill_order(
+    context: &DecoderContext,
+    swap: &impl Fn(Address, Address, Address, U256, U256) -> Swap,
+    order: &AggregationRouterV6::Order,
+    taker_traits: U256,
+    args: &[u8],
+) -> Result<Swap, DecodeError> {
+    let target = match (taker_traits.bit(consts::ARGS_HAS_TARGET), args.get(..20)) {
+        (true, Some(target)) => Address::from_slice(target),
+        _ => context.tx().from(),
+    };
+    // every fill variant returns (making, taking, orderHash); `OrderFilled`
+    // only reports what is left of the order, so it just confirms the fill
+    let returned = AggregationRouterV6::fillOrderCall::abi_decode_returns(context.output()?, true)?;
+    let (making, taking, order_hash) = (returned._0, returned._1, returned._2);
+    context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::ROUTER && !log.removed)
+        .filter_map(decode_event::<AggregationRouterV6::OrderFilled>)
+        .find(|filled| filled.orderHash == order_hash)
+        .ok_or(DecodeError::MissingLogs(consts::ROUTER))?;
+    Ok(swap(
+        target,
+        word_address(order.takerAsset),
+        word_address(order.makerAsset),
+        taking,
+        making,
+    ))
+}
+
+/// `returnAmount` of a `clipperSwap*` call.
+fn clipper_return_amount(context: &DecoderContext) -> Result<U256, DecodeError> {
+    let returned =
+        AggregationRouterV6::clipperSwapCall::abi_decode_returns(context.output()?, true)?;
+    Ok(returned.returnAmount)
+}
+
+/// The address packed in the low 160 bits of v6's `Address` type.
+fn word_address(word: U256) -> Address {
+    Address::from_word(B256::from(word))
+}
+
+/// A packed v6 `dex` word: the pool address in the low 160 bits, the
+/// protocol in the top three bits and per-protocol flags and arguments in
+/// between.
+#[derive(Debug, Clone, Copy)]
+struct Dex {
+    pool: Address,
+    kind: DexKind,
+    /// WETH received from this pool is unwrapped to ETH
+    unwrap_weth: bool,
+    /// ETH is sent to the pool as is instead of being wrapped first
+    not_wrap_weth: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DexKind {
+    UniswapV2 { zero_for_one: bool, numerator: u32 },
+    UniswapV3 { zero_for_one: bool },
+    Curve { i: u8, j: u8 },
+}
+
+impl TryFrom<U256> for Dex {
+    type Error = DecodeError;
+
+    fn try_from(word: U256) -> Result<Self, Self::Error> {
+        let pool = word_address(word);
+        let field = |offset: usize, mask: u32| ((word >> offset) & U256::from(mask)).to::<u32>();
+        let zero_for_one = word.bit(consts::ZERO_FOR_ONE_FLAG);
+        let kind = match field(consts::PROTOCOL_OFFSET, 0b111) {
+            0 => DexKind::UniswapV2 {
+                zero_for_one,
+                numerator: field(consts::UNISWAP_V2_NUMERATOR_OFFSET, u32::MAX),
+            },
+            1 => DexKind::UniswapV3 { zero_for_one },
+            2 => DexKind::Curve {
+                i: field(consts::CURVE_FROM_TOKEN_OFFSET, 0xff) as u8,
+                j: field(consts::CURVE_TO_TOKEN_OFFSET, 0xff) as u8,
+            },
+            protocol => return Err(DecodeError::UnsupportedProtocol(protocol)),
+        };
+        Ok(Self {
+            pool,
+            kind,
+            unwrap_weth: word.bit(consts::WETH_UNWRAP_FLAG),
+            not_wrap_weth: word.bit(consts::WETH_NOT_WRAP_FLAG),
+        })
+    }
+}
+
+/// Decodes the hops of an `unoswap*` call. As with v5, the tokens are not
+/// part of the dex words: each hop's output is what its pool paid out.
+/// Uniswap amounts come from the pools' `Swap` events, Curve ones from the
+/// payments made within the pool's exchange call.
+fn unoswap_decode_swap(
+    context: &DecoderContext,
+    src_token: Address,
+    dexes: &[U256],
+) -> Result<SwapEntry, DecodeError> {
+    let logs = context.logs()?;
+    let dexes = dexes
+        .iter()
+        .map(|word| Dex::try_from(*word))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = dexes.first().ok_or(DecodeError::EmptyPath)?;
+    let mut token_in = match is_eth(&src_token) && !first.not_wrap_weth {
+        true => balance::consts::WETH,
+        false => src_token,
+    };
+
+    let trace = &context.trace()?.trace;
+    let mut legs = vec![];
+    for (index, dex) in dexes.iter().enumerate() {
+        let visit = dexes[..index].iter().filter(|d| d.pool == dex.pool).count();
+        let leg = match dex.kind {
+            DexKind::Curve { i, j } => {
+                let (token_out, amount_in, amount_out) =
+                    curve_frame_amounts(trace, &dex.pool, visit, token_in)?;
+                SwapLeg {
+                    protocol: Protocol::Curve { i, j },
+                    pool: dex.pool,
+                    fee: 0,
+                    token_in,
+                    token_out,
+                    amount_in,
+                    amount_out,
+                    zero_for_one: i < j,
+                }
+            }
+            DexKind::UniswapV2 {
+                zero_for_one,
+                numerator,
+            } => {
+                let pool = Pool {
+                    token_in,
+                    token_out: pool_token_out(trace, &dex.pool, visit)?,
+                    fee: 1_000_000_000u32.saturating_sub(numerator) / 1000,
+                    pool: dex.pool,
+                    reverse: !zero_for_one,
+                };
+                v2_decode_leg(&consts::ROUTER, &pool, logs)?
+            }
+            DexKind::UniswapV3 { zero_for_one } => {
+                let token_out = pool_token_out(trace, &dex.pool, visit)?;
+                let pool = Pool {
+                    token_in,
+                    token_out,
+                    fee: v3_pool_fee(token_in, token_out, &dex.pool),
+                    pool: dex.pool,
+                    reverse: !zero_for_one,
+                };
+                v3_decode_leg(&consts::ROUTER, &pool, logs)?
+            }
+        };
+        token_in = leg.token_out;
+        legs.push(leg);
+    }
+
+    let mut entry = SwapEntry::from_legs(legs)?;
+    if dexes.last().is_some_and(|dex| dex.unwrap_weth) {
+        entry.token_out = balance::consts::ETH;
+    }
+    Ok(entry)
+}
+
+/// Output token and the amounts in and out of the `visit`-th exchange call to
+/// the Curve `pool`, from the payments made within that call: `token_in` is
+/// pulled by the pool or sent along as value, the output is the ERC-20 the
+/// pool transferred out, or ETH sent as value.
+fn curve_frame_amounts(
+    trace: &[TransactionTrace],
+    pool: &Address,
+    visit: usize,
+    token_in: Address,
+) -> Result<(Address, U256, U256), DecodeError> {
+    use balance::ERC20::ERC20Calls as E;
+    let frame = pool_frame(trace, pool, visit)?;
+    let mut amount_in = match &frame.action {
+        Action::Call(call) if is_eth(&token_in) => call.value,
+        _ => U256::ZERO,
+    };
+    let mut output = None;
+    for call in frame_calls(trace, frame) {
+        match E::abi_decode(&call.input, true) {
+            Ok(E::transferFrom(transfer)) if transfer.to == *pool && call.to == token_in => {
+                amount_in = transfer.value;
+            }
+            Ok(E::transfer(transfer)) if call.from == *pool && output.is_none() => {
+                output = Some((call.to, transfer.value));
+            }
+            _ if call.from == *pool && !call.value.is_zero() && output.is_none() => {
+                output = Some((balance::consts::ETH, call.value));
+            }
+            _ => {}
+        }
+    }
+    let (token_out, amount_out) = output.ok_or(DecodeError::UnresolvedToken(*pool))?;
+    Ok((token_out, amount_in, amount_out))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, uint};
+
+    use super::*;
+
+    #[test]
+    fn uniswap_dex_words() {
+        // USDC to WETH through the 0.3% pair
+        let dex = Dex::try_from(uint!(
+            0x00800000000000003b6d0340b4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256
+        ))
+        .unwrap();
+        assert_eq!(
+            dex.pool,
+            address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")
+        );
+        assert!(matches!(
+            dex.kind,
+            DexKind::UniswapV2 {
+                zero_for_one: true,
+                numerator: 997_000_000
+            }
+        ));
+        assert!(!dex.unwrap_weth && !dex.not_wrap_weth);
+
+        // USDC to ETH through the 0.05% pool
+        let dex = Dex::try_from(uint!(
+            0x30000000000000000000000088e6a0c2ddd26feeb64f039a2c41296fcb3f5640_U256
+        ))
+        .unwrap();
+        assert_eq!(
+            dex.pool,
+            address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")
+        );
+        assert!(matches!(
+            dex.kind,
+            DexKind::UniswapV3 {
+                zero_for_one: false
+            }
+        ));
+        assert!(dex.unwrap_weth);
+    }
+
+    #[test]
+    fn curve_dex_word() {
+        // coin 1 for coin 0 of 3pool, the coins arguments set to 5 and 7
+        let dex = Dex::try_from(uint!(
+            0x400000010500070000000000bebc44782c7db0a1a60cb6fe97d0b483032ff1c7_U256
+        ))
+        .unwrap();
+        assert_eq!(
+            dex.pool,
+            address!("bEbc44782C7dB0a1A60Cb6fe97d0b483032FF1C7")
+        );
+        assert!(matches!(dex.kind, DexKind::Curve { i: 1, j: 0 }));
+    }
+
+    #[test]
+    fn unsupported_dex_protocol() {
+        let word = uint!(0x600000000000000000000000bebc44782c7db0a1a60cb6fe97d0b483032ff1c7_U256);
+        assert!(matches!(
+            Dex::try_from(word),
+            Err(DecodeError::UnsupportedProtocol(3))
+        ));
+    }
+}
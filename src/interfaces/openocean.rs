@@ -0,0 +1,178 @@
+use alloy::{
+    primitives::{Address, B128, U256},
+    providers::network::TransactionResponse,
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface},
+};
+
+use super::{
+    balance::decode_event,
+    oneinch_v5::{uniswap_v3_decode_swap, unoswap_decode_swap},
+    DecodeError, Decoder, DecoderContext, Partner, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "OpenOcean Exchange";
+    /// OpenOcean's exchange proxy. 0xe66B31678d6C16E9ebf358268a790B763C133750,
+    /// which was requested as OpenOcean's, answers ZeroExProxy's `proxiedSwap`
+    /// and `optimalSwap` and stays with `zerox.rs` until that is confirmed
+    pub const EXCHANGE: Address = address!("6352a56caadC4F1E25CD6c75970Fa768A3304e64");
+}
+
+sol! {
+
+// cast interface 0x6352a56caadc4f1e25cd6c75970fa768a3304e64
+interface OpenOceanExchange {
+    struct SwapDescription {
+        address srcToken;
+        address dstToken;
+        address srcReceiver;
+        address dstReceiver;
+        uint256 amount;
+        uint256 minReturnAmount;
+        uint256 guaranteedAmount;
+        uint256 flags;
+        address referrer;
+        bytes permit;
+    }
+
+    struct CallDescription {
+        uint256 target;
+        uint256 gasLimit;
+        uint256 value;
+        bytes data;
+    }
+
+    event Swapped(
+        address indexed sender,
+        address indexed srcToken,
+        address indexed dstToken,
+        address dstReceiver,
+        uint256 amount,
+        uint256 spentAmount,
+        uint256 returnAmount,
+        uint256 minReturnAmount,
+        uint256 guaranteedAmount,
+        address referrer
+    );
+
+    function callUniswapTo(
+        address srcToken,
+        uint256 amount,
+        uint256 minReturn,
+        bytes32[] calldata pools,
+        address payable recipient
+    ) external payable returns (uint256 returnAmount);
+    function swap(address caller, SwapDescription calldata desc, CallDescription[] calldata calls)
+        external
+        payable
+        returns (uint256 returnAmount);
+    function uniswapV3SwapTo(address payable recipient, uint256 amount, uint256 minReturn, uint256[] calldata pools)
+        external
+        payable
+        returns (uint256 returnAmount);
+}
+
+}
+
+pub struct DecoderOpenOcean {}
+
+impl DecoderOpenOcean {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderOpenOcean {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::EXCHANGE]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use OpenOceanExchange as C;
+        vec![
+            C::swapCall::SELECTOR,
+            C::uniswapV3SwapToCall::SELECTOR,
+            C::callUniswapToCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use OpenOceanExchange::OpenOceanExchangeCalls as C;
+        let tx = context.tx();
+        let router = tx.to().unwrap();
+
+        let mut swap = match C::abi_decode(&tx.input, true)? {
+            C::swap(_) => {
+                let swapped = context
+                    .logs()?
+                    .iter()
+                    .filter(|log| log.address() == router && !log.removed)
+                    .find_map(decode_event::<OpenOceanExchange::Swapped>)
+                    .ok_or(DecodeError::MissingLogs(router))?;
+                let partner = match swapped.referrer.is_zero() {
+                    true => None,
+                    false => Some(Partner {
+                        address: swapped.referrer,
+                        fee_percent: U256::ZERO,
+                        uuid: B128::ZERO,
+                    }),
+                };
+                Swap {
+                    partner,
+                    ..Swap::new(
+                        tx.from(),
+                        swapped.dstReceiver,
+                        swapped.srcToken,
+                        swapped.dstToken,
+                        swapped.spentAmount,
+                        swapped.returnAmount,
+                    )
+                }
+            }
+            // pools are packed like 1inch's `uniswapV3Swap` pools
+            C::uniswapV3SwapTo(call) => {
+                let entry = uniswap_v3_decode_swap(context, &router, None, &call.pools)?;
+                Swap {
+                    legs: entry.legs,
+                    ..Swap::new(
+                        tx.from(),
+                        call.recipient,
+                        entry.token_in,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+            }
+            // and these like 1inch's `unoswap` pools
+            C::callUniswapTo(call) => {
+                let pools = call
+                    .pools
+                    .iter()
+                    .map(|pool| U256::from_be_bytes(pool.0))
+                    .collect::<Vec<_>>();
+                let entry = unoswap_decode_swap(context, &router, call.srcToken, &pools)?;
+                Swap {
+                    legs: entry.legs,
+                    ..Swap::new(
+                        tx.from(),
+                        call.recipient,
+                        call.srcToken,
+                        entry.token_out,
+                        call.amount,
+                        entry.amount_out,
+                    )
+                }
+            }
+        };
+        context.balance_deltas()?.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
@@ -1,4 +1,35 @@
-use alloy::{rpc::types::Transaction, sol, sol_types::SolInterface};
+use std::collections::HashMap;
+
+use alloy::{
+    primitives::{Address, B128, B256, U256},
+    rpc::types::Log,
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface, SolValue},
+};
+
+use super::{
+    balance::{self, decode_event, is_eth},
+    extract_selector,
+    universal_router::{pool_token_out, v2_decode_leg, v3_decode_swap, Pool, SwapType},
+    DecodeError, Decoder, DecoderContext, Partner, Protocol, RouteSplit, Swap, SwapLeg,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "ParaSwap Augustus V5";
+    pub const ROUTER: Address = address!("DEF171Fe48CF0115B1d80b88dc8eAB59176FEe57");
+
+    // `Route.targetExchange`s whose payload is decoded into hops
+    pub const UNISWAP_V2_ROUTER: Address = address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+    pub const SUSHISWAP_ROUTER: Address = address!("d9e1cE17f2641f24aE83637ab66a2cca9C378B9F");
+    pub const UNISWAP_V3_ROUTER: Address = address!("E592427A0AEce92De3Edee1F18E0157C05861564");
+    pub const BALANCER_V2_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+
+    // UniswapV2 payload pool words
+    pub const UNISWAP_V2_FEE_OFFSET: usize = 161;
+    pub const PERCENT_BASE: u32 = 10_000;
+}
 
 sol! {
 
@@ -266,3 +297,551 @@ interface DirectSwap {
 }
 
 }
+
+sol! {
+
+interface BalancerVault {
+    event Swap(
+        bytes32 indexed poolId,
+        address indexed tokenIn,
+        address indexed tokenOut,
+        uint256 amountIn,
+        uint256 amountOut
+    );
+}
+
+// `Route.payload` of the adapters' exchanges
+interface Payloads {
+    struct BatchSwapStep {
+        bytes32 poolId;
+        uint256 assetInIndex;
+        uint256 assetOutIndex;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address payable recipient;
+        bool toInternalBalance;
+    }
+
+    struct UniswapV2Data {
+        address weth;
+        uint256[] pools;
+    }
+
+    struct UniswapV3Data {
+        bytes path;
+        uint256 deadline;
+    }
+
+    struct BalancerData {
+        BatchSwapStep[] swaps;
+        address[] assets;
+        FundManagement funds;
+        int256[] limits;
+        uint256 deadline;
+    }
+}
+
+}
+
+pub struct DecoderParaSwapV5 {}
+
+impl DecoderParaSwapV5 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderParaSwapV5 {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use DirectSwap as D;
+        use MultiPath as C;
+        vec![
+            C::multiSwapCall::SELECTOR,
+            C::megaSwapCall::SELECTOR,
+            C::buyCall::SELECTOR,
+            D::directUniV3SwapCall::SELECTOR,
+            D::directUniV3BuyCall::SELECTOR,
+            D::directCurveV1SwapCall::SELECTOR,
+            D::directCurveV2SwapCall::SELECTOR,
+            D::directBalancerV2GivenInSwapCall::SELECTOR,
+            D::directBalancerV2GivenOutSwapCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let selector = extract_selector(&context.tx().input)?;
+        let swap = match DirectSwap::DirectSwapCalls::valid_selector(selector) {
+            true => direct_decode_swap(context)?,
+            false => multi_path_decode_swap(context)?,
+        };
+        Ok(vec![swap])
+    }
+}
+
+fn multi_path_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use MultiPath::MultiPathCalls as C;
+    let full = U256::from(consts::PERCENT_BASE);
+    let mut visits = HashMap::new();
+    let (mut swap, route) = match C::abi_decode(&context.tx().input, true)? {
+        C::multiSwap(call) => {
+            let data = call.data;
+            let route = expand_paths(context, &mut visits, 0, full, data.fromToken, &data.path)?;
+            (
+                settled_swap::<MultiPath::SwappedV3>(context, data.uuid)?,
+                route,
+            )
+        }
+        C::megaSwap(call) => {
+            let data = call.data;
+            let mut route = vec![];
+            for (index, path) in data.path.iter().enumerate() {
+                route.extend(expand_paths(
+                    context,
+                    &mut visits,
+                    index,
+                    path.fromAmountPercent,
+                    data.fromToken,
+                    &path.path,
+                )?);
+            }
+            (
+                settled_swap::<MultiPath::SwappedV3>(context, data.uuid)?,
+                route,
+            )
+        }
+        C::buy(call) => {
+            let data = call.data;
+            let adapter = MultiPath::Adapter {
+                adapter: data.adapter,
+                percent: full,
+                networkFee: U256::ZERO,
+                route: data.route,
+            };
+            let path = MultiPath::Path {
+                to: data.toToken,
+                totalNetworkFee: U256::ZERO,
+                adapters: vec![adapter],
+            };
+            let route = expand_paths(context, &mut visits, 0, full, data.fromToken, &[path])?;
+            (
+                settled_swap::<MultiPath::BoughtV3>(context, data.uuid)?,
+                route,
+            )
+        }
+        call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+    };
+    swap.legs = route.iter().flat_map(|split| split.legs.clone()).collect();
+    swap.route = route;
+    Ok(swap)
+}
+
+/// Flattens one `Path[]` of the route tree. Each `Path` is a step to its `to`
+/// token, split between adapters and again between each adapter's routes;
+/// the two percentages multiply into the share of the step's input.
+/// `visits` counts the calls already decoded per pool, across paths.
+fn expand_paths(
+    context: &DecoderContext,
+    visits: &mut HashMap<Address, usize>,
+    index: usize,
+    path_percent: U256,
+    from_token: Address,
+    paths: &[MultiPath::Path],
+) -> Result<Vec<RouteSplit>, DecodeError> {
+    let base = U256::from(consts::PERCENT_BASE);
+    let mut splits = vec![];
+    let mut token_in = from_token;
+    for (step, path) in paths.iter().enumerate() {
+        for adapter in &path.adapters {
+            for route in &adapter.route {
+                splits.push(RouteSplit {
+                    path: index,
+                    step,
+                    path_percent: path_percent.saturating_to(),
+                    percent: (adapter.percent * route.percent / base).saturating_to(),
+                    token_in,
+                    token_out: path.to,
+                    exchange: route.targetExchange,
+                    legs: route_decode_legs(context, visits, token_in, path.to, route)?,
+                });
+            }
+        }
+        token_in = path.to;
+    }
+    Ok(splits)
+}
+
+/// Fee tier of a UniswapV2 payload pool word, in hundredths of a bip. The
+/// word carries the fee numerator out of 10000, left empty by some forks.
+fn uniswap_v2_pool_fee(word: U256) -> u32 {
+    match (word >> consts::UNISWAP_V2_FEE_OFFSET).saturating_to::<u32>() {
+        0 => 0,
+        numerator => consts::PERCENT_BASE.saturating_sub(numerator) * 100,
+    }
+}
+
+/// Decodes the `payload` of routes through exchanges we know the adapter
+/// encoding of. Other exchanges get no legs.
+fn route_decode_legs(
+    context: &DecoderContext,
+    visits: &mut HashMap<Address, usize>,
+    token_in: Address,
+    token_out: Address,
+    route: &MultiPath::Route,
+) -> Result<Vec<SwapLeg>, DecodeError> {
+    let logs = context.logs()?;
+    let trace = &context.trace()?.trace;
+    // adapters wrap ETH before handing it to a pool
+    let pool_token = |token: Address| match is_eth(&token) {
+        true => balance::consts::WETH,
+        false => token,
+    };
+    let legs = match route.targetExchange {
+        consts::UNISWAP_V2_ROUTER | consts::SUSHISWAP_ROUTER => {
+            let data = Payloads::UniswapV2Data::abi_decode(&route.payload, true)?;
+            let mut token_in = pool_token(token_in);
+            let mut legs = vec![];
+            for (n, word) in data.pools.iter().enumerate() {
+                let pair = Address::from_word(B256::from(*word));
+                let visit = visits.entry(pair).or_default();
+                // the pairs are called directly, out of the adapter the
+                // router delegates to, with the intermediate tokens implied
+                // by what each pair sent out within its own swap call
+                let hop_out = match n + 1 == data.pools.len() {
+                    true => pool_token(token_out),
+                    false => pool_token_out(trace, &pair, *visit)?,
+                };
+                *visit += 1;
+                let pool = Pool {
+                    token_in,
+                    token_out: hop_out,
+                    fee: uniswap_v2_pool_fee(*word),
+                    pool: pair,
+                    reverse: token_in > hop_out,
+                };
+                let leg = v2_decode_leg(&consts::ROUTER, &pool, logs)?;
+                token_in = leg.token_out;
+                legs.push(leg);
+            }
+            legs
+        }
+        consts::UNISWAP_V3_ROUTER => {
+            let data = Payloads::UniswapV3Data::abi_decode(&route.payload, true)?;
+            v3_decode_swap(&route.targetExchange, SwapType::ExactIn, &data.path, logs)?.legs
+        }
+        consts::BALANCER_V2_VAULT => {
+            let data = Payloads::BalancerData::abi_decode(&route.payload, true)?;
+            let steps = data
+                .swaps
+                .iter()
+                .map(|step| (step.poolId, step.assetInIndex, step.assetOutIndex))
+                .collect::<Vec<_>>();
+            balancer_decode_legs(
+                logs,
+                route.targetExchange,
+                &steps,
+                &data.assets,
+                SwapType::ExactIn,
+            )?
+        }
+        _ => vec![],
+    };
+    Ok(legs)
+}
+
+/// Direct swaps skip the adapters and call a single DEX from the router, so
+/// the hops are known from the calldata alone.
+fn direct_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use DirectSwap::DirectSwapCalls as C;
+    let logs = context.logs()?;
+    let (uuid, legs) = match C::abi_decode(&context.tx().input, true)? {
+        C::directUniV3Swap(call) => {
+            let data = call.data;
+            let entry = v3_decode_swap(&data.exchange, SwapType::ExactIn, &data.path, logs)?;
+            (data.uuid, entry.legs)
+        }
+        C::directUniV3Buy(call) => {
+            let data = call.data;
+            let entry = v3_decode_swap(&data.exchange, SwapType::ExactOut, &data.path, logs)?;
+            (data.uuid, entry.legs)
+        }
+        C::directCurveV1Swap(call) => {
+            let data = call.data;
+            let leg = curve_leg(
+                context,
+                data.exchange,
+                (data.fromToken, data.toToken),
+                data.needWrapNative,
+                coin_index(data.exchange, data.i)?,
+                coin_index(data.exchange, data.j)?,
+            )?;
+            (data.uuid, vec![leg])
+        }
+        C::directCurveV2Swap(call) => {
+            let data = call.data;
+            // `exchange` is a zap for factory pools, which sets `poolAddress`
+            let pool = match data.poolAddress.is_zero() {
+                true => data.exchange,
+                false => data.poolAddress,
+            };
+            let leg = curve_leg(
+                context,
+                pool,
+                (data.fromToken, data.toToken),
+                data.needWrapNative,
+                coin_index(pool, data.i)?,
+                coin_index(pool, data.j)?,
+            )?;
+            (data.uuid, vec![leg])
+        }
+        C::directBalancerV2GivenInSwap(call) => {
+            let data = call.data;
+            let steps = data
+                .swaps
+                .iter()
+                .map(|step| (step.poolId, step.assetInIndex, step.assetOutIndex))
+                .collect::<Vec<_>>();
+            let legs =
+                balancer_decode_legs(logs, data.vault, &steps, &data.assets, SwapType::ExactIn)?;
+            (data.uuid, legs)
+        }
+        C::directBalancerV2GivenOutSwap(call) => {
+            let data = call.data;
+            let steps = data
+                .swaps
+                .iter()
+                .map(|step| (step.poolId, step.assetInIndex, step.assetOutIndex))
+                .collect::<Vec<_>>();
+            let legs =
+                balancer_decode_legs(logs, data.vault, &steps, &data.assets, SwapType::ExactOut)?;
+            (data.uuid, legs)
+        }
+        call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+    };
+    let mut swap = settled_swap::<DirectSwap::SwappedDirect>(context, uuid)?;
+    swap.legs = legs;
+    Ok(swap)
+}
+
+/// The swap as reported by the router's event for the call tagged `uuid`:
+/// the amounts it settled with the beneficiary, after partner and positive
+/// slippage fees.
+fn settled_swap<E>(context: &DecoderContext, uuid: B128) -> Result<Swap, DecodeError>
+where
+    E: SolEvent,
+    Swap: From<E>,
+{
+    context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::ROUTER && !log.removed)
+        .filter_map(decode_event::<E>)
+        .map(Swap::from)
+        .find(|swap| {
+            swap.partner
+                .as_ref()
+                .is_some_and(|partner| partner.uuid == uuid)
+        })
+        .ok_or(DecodeError::MissingLogs(consts::ROUTER))
+}
+
+/// Curve pools don't emit anything the router can be matched on, so the
+/// amounts are the pool's balance changes. Swaps through a lending pool's
+/// underlying coins move no balance of the pool itself and are left at zero.
+fn curve_leg(
+    context: &DecoderContext,
+    pool: Address,
+    (token_in, token_out): (Address, Address),
+    need_wrap_native: bool,
+    i: u8,
+    j: u8,
+) -> Result<SwapLeg, DecodeError> {
+    // the router wraps ETH for pools that only hold WETH
+    let pool_token = |token: Address| match is_eth(&token) && need_wrap_native {
+        true => balance::consts::WETH,
+        false => token,
+    };
+    let (token_in, token_out) = (pool_token(token_in), pool_token(token_out));
+    let deltas = context.balance_deltas()?;
+    Ok(SwapLeg {
+        protocol: Protocol::Curve { i, j },
+        pool,
+        fee: 0,
+        token_in,
+        token_out,
+        amount_in: deltas.received(&pool, &token_in),
+        amount_out: deltas.spent(&pool, &token_out),
+        zero_for_one: i < j,
+    })
+}
+
+/// A Curve coin index from calldata, rejected rather than wrapped when it
+/// doesn't fit a `u8`.
+fn coin_index<T: TryInto<u8>>(pool: Address, index: T) -> Result<u8, DecodeError> {
+    index
+        .try_into()
+        .map_err(|_| DecodeError::InvalidCoinIndex(pool))
+}
+
+/// Expands a Vault batch swap into one leg per `BatchSwapStep`, with the
+/// amounts of the Vault's matching `Swap` event. Given-out batches list their
+/// steps from the bought token backwards and are reversed into execution
+/// order.
+fn balancer_decode_legs(
+    logs: &[Log],
+    vault: Address,
+    steps: &[(B256, U256, U256)],
+    assets: &[Address],
+    swap_type: SwapType,
+) -> Result<Vec<SwapLeg>, DecodeError> {
+    let mut swap_logs = logs
+        .iter()
+        .filter(|log| log.address() == vault && !log.removed)
+        .filter_map(decode_event::<BalancerVault::Swap>)
+        .collect::<Vec<_>>();
+    // the Vault trades WETH for the zero address
+    let asset = |index: U256| {
+        let token = assets
+            .get(index.saturating_to::<usize>())
+            .ok_or(DecodeError::EmptyPath)?;
+        Ok::<_, DecodeError>(match token.is_zero() {
+            true => balance::consts::WETH,
+            false => *token,
+        })
+    };
+
+    let mut legs = vec![];
+    for (pool_id, asset_in, asset_out) in steps {
+        let pool = Address::from_slice(&pool_id[..20]);
+        let (token_in, token_out) = (asset(*asset_in)?, asset(*asset_out)?);
+        // the same pool can be traded more than once, consume the events in order
+        let position = swap_logs
+            .iter()
+            .position(|swap| {
+                swap.poolId == *pool_id && swap.tokenIn == token_in && swap.tokenOut == token_out
+            })
+            .ok_or(DecodeError::MissingLogs(pool))?;
+        let swap = swap_logs.remove(position);
+        legs.push(SwapLeg {
+            protocol: Protocol::BalancerV2 { pool_id: *pool_id },
+            pool,
+            fee: 0,
+            token_in,
+            token_out,
+            amount_in: swap.amountIn,
+            amount_out: swap.amountOut,
+            zero_for_one: token_in < token_out,
+        });
+    }
+    if let SwapType::ExactOut = swap_type {
+        legs.reverse();
+    }
+    Ok(legs)
+}
+
+impl From<MultiPath::SwappedV3> for Swap {
+    fn from(event: MultiPath::SwappedV3) -> Self {
+        Swap {
+            partner: Some(Partner {
+                address: event.partner,
+                fee_percent: event.feePercent,
+                uuid: event.uuid,
+            }),
+            ..Swap::new(
+                event.initiator,
+                event.beneficiary,
+                event.srcToken,
+                event.destToken,
+                event.srcAmount,
+                event.receivedAmount,
+            )
+        }
+    }
+}
+
+impl From<MultiPath::BoughtV3> for Swap {
+    fn from(event: MultiPath::BoughtV3) -> Self {
+        Swap {
+            partner: Some(Partner {
+                address: event.partner,
+                fee_percent: event.feePercent,
+                uuid: event.uuid,
+            }),
+            ..Swap::new(
+                event.initiator,
+                event.beneficiary,
+                event.srcToken,
+                event.destToken,
+                event.srcAmount,
+                event.receivedAmount,
+            )
+        }
+    }
+}
+
+impl From<DirectSwap::SwappedDirect> for Swap {
+    fn from(event: DirectSwap::SwappedDirect) -> Self {
+        Swap {
+            partner: Some(Partner {
+                address: event.partner,
+                fee_percent: event.feePercent,
+                uuid: event.uuid,
+            }),
+            ..Swap::new(
+                event.initiator,
+                event.beneficiary,
+                event.srcToken,
+                event.destToken,
+                event.srcAmount,
+                event.receivedAmount,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::uint;
+
+    use super::*;
+
+    #[test]
+    fn uniswap_v2_fee_word() {
+        // 9970 out of 10000, the pair in the low 160 bits
+        let word = uint!(0x4de4b4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256);
+        assert_eq!(uniswap_v2_pool_fee(word), 3000);
+        // the direction bit doesn't touch the fee
+        let word = uint!(0x4de5b4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256);
+        assert_eq!(uniswap_v2_pool_fee(word), 3000);
+        // forks that leave the fee empty
+        let word = uint!(0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc_U256);
+        assert_eq!(uniswap_v2_pool_fee(word), 0);
+    }
+
+    #[test]
+    fn curve_coin_indices() {
+        let pool = Address::ZERO;
+        assert_eq!(coin_index(pool, 2i128).unwrap(), 2);
+        assert_eq!(coin_index(pool, U256::from(1)).unwrap(), 1);
+        assert!(matches!(
+            coin_index(pool, -1i128),
+            Err(DecodeError::InvalidCoinIndex(_))
+        ));
+        assert!(matches!(
+            coin_index(pool, U256::from(256)),
+            Err(DecodeError::InvalidCoinIndex(_))
+        ));
+    }
+}
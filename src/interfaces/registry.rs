@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{hex, Address};
+
+use super::{
+    extract_selector, DecodeError, DecodedTx, Decoder, DecoderContext, DecoderGPv2Settlement,
+    DecoderKyberSwap, DecoderMetaMaskSwapRouter, DecoderOneInchV4, DecoderOneInchV5,
+    DecoderOneInchV6, DecoderOpenOcean, DecoderParaSwapV5, DecoderUniswapV2, DecoderUniswapV3,
+    DecoderUniswapV3SwapRouter, DecoderUnivesalRouter, DecoderZeroEx, DecoderZeroExProxy, TxPos,
+};
+
+/// Result of looking a transaction up in the registry.
+#[derive(Debug, Clone)]
+pub enum DecodeOutcome {
+    Decoded(DecodedTx),
+    /// no registered decoder handles this (to address, selector) pair
+    NoDecoder {
+        to: Address,
+        selector: [u8; 4],
+    },
+}
+
+/// Returned by [`DecoderRegistry::register`] when a decoder claims an
+/// (address, selector) pair that another decoder already owns.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{decoder} conflicts with {}", display_conflicts(.conflicts))]
+pub struct AmbiguousRegistration {
+    pub decoder: String,
+    /// (address, selector, name of the decoder already registered for it)
+    pub conflicts: Vec<(Address, [u8; 4], String)>,
+}
+
+fn display_conflicts(conflicts: &[(Address, [u8; 4], String)]) -> String {
+    conflicts
+        .iter()
+        .map(|(address, selector, decoder)| {
+            format!("{} on {}:0x{}", decoder, address, hex::encode(selector))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Decoders indexed by the (to address, selector) pairs they support.
+///
+/// `supported_address()` and `supported_selectors()` are only queried once,
+/// at registration.
+pub struct DecoderRegistry {
+    decoders: Vec<Box<dyn Decoder>>,
+    index: HashMap<(Address, [u8; 4]), usize>,
+}
+
+impl Default for DecoderRegistry {
+    /// A registry holding every decoder shipped with this crate.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        let builtin: Vec<Box<dyn Decoder>> = vec![
+            Box::new(DecoderUnivesalRouter::new()),
+            Box::new(DecoderOneInchV4::new()),
+            Box::new(DecoderOneInchV5::new()),
+            Box::new(DecoderOneInchV6::new()),
+            Box::new(DecoderParaSwapV5::new()),
+            Box::new(DecoderZeroEx::new()),
+            Box::new(DecoderZeroExProxy::new()),
+            Box::new(DecoderMetaMaskSwapRouter::new()),
+            Box::new(DecoderUniswapV2::new()),
+            Box::new(DecoderUniswapV3::new()),
+            Box::new(DecoderUniswapV3SwapRouter::new()),
+            Box::new(DecoderGPv2Settlement::new()),
+            Box::new(DecoderKyberSwap::new()),
+            Box::new(DecoderOpenOcean::new()),
+        ];
+        for decoder in builtin {
+            registry.register(decoder).expect("builtin decoders");
+        }
+        registry
+    }
+}
+
+impl DecoderRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self {
+            decoders: vec![],
+            index: HashMap::new(),
+        }
+    }
+
+    /// Adds `decoder` for every (address, selector) pair it supports. Nothing
+    /// is registered if any of the pairs is already taken.
+    pub fn register(&mut self, decoder: Box<dyn Decoder>) -> Result<(), AmbiguousRegistration> {
+        let keys = decoder
+            .supported_address()
+            .into_iter()
+            .flat_map(|address| {
+                decoder
+                    .supported_selectors()
+                    .into_iter()
+                    .map(move |selector| (address, selector))
+            })
+            .collect::<Vec<_>>();
+        let conflicts = keys
+            .iter()
+            .filter_map(|key| {
+                let registered = self.index.get(key)?;
+                Some((key.0, key.1, self.decoders[*registered].name()))
+            })
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            return Err(AmbiguousRegistration {
+                decoder: decoder.name(),
+                conflicts,
+            });
+        }
+
+        let position = self.decoders.len();
+        self.decoders.push(decoder);
+        self.index
+            .extend(keys.into_iter().map(|key| (key, position)));
+        Ok(())
+    }
+
+    pub fn get(&self, to: &Address, selector: &[u8; 4]) -> Option<&dyn Decoder> {
+        let position = self.index.get(&(*to, *selector))?;
+        Some(self.decoders[*position].as_ref())
+    }
+
+    pub fn decode(&self, rpc_url: String, pos: TxPos) -> Result<DecodeOutcome, DecodeError> {
+        let context = DecoderContext::new(rpc_url, pos)?;
+        let tx = context.tx();
+        let to = tx.to.ok_or(DecodeError::ContractCreation)?;
+        let selector = extract_selector(&tx.input)?;
+
+        let decoder = match self.get(&to, &selector) {
+            Some(decoder) => decoder,
+            None => return Ok(DecodeOutcome::NoDecoder { to, selector }),
+        };
+        if !context.receipt()?.status() {
+            return Err(DecodeError::RevertedTx(tx.hash));
+        }
+        let swaps = decoder.decode(&context)?;
+        let interactions = decoder.interactions(&context)?;
+        let nft_purchases = decoder.nft_purchases(&context)?;
+        Ok(DecodeOutcome::Decoded(DecodedTx {
+            tx_hash: tx.hash,
+            block_number: tx.block_number,
+            transaction_index: tx.transaction_index,
+            decoder: decoder.name(),
+            swaps,
+            interactions,
+            nft_purchases,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, hex};
+
+    use super::*;
+    use crate::interfaces::Swap;
+
+    const UNIVERSAL_ROUTER: Address = address!("3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD");
+    const OPENOCEAN_EXCHANGE: Address = address!("6352a56caadC4F1E25CD6c75970Fa768A3304e64");
+    const ZEROEX_PROXY: Address = address!("e66B31678d6C16E9ebf358268a790B763C133750");
+
+    struct Stub {
+        address: Address,
+        selectors: Vec<[u8; 4]>,
+    }
+
+    impl Decoder for Stub {
+        fn name(&self) -> String {
+            "stub".to_string()
+        }
+
+        fn supported_address(&self) -> Vec<Address> {
+            vec![self.address]
+        }
+
+        fn supported_selectors(&self) -> Vec<[u8; 4]> {
+            self.selectors.clone()
+        }
+
+        fn decode(&self, _context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn builtin_decoders_do_not_conflict() {
+        let registry = DecoderRegistry::default();
+        // execute(bytes,bytes[],uint256)
+        let decoder = registry.get(&UNIVERSAL_ROUTER, &hex!("3593564c")).unwrap();
+        assert_eq!(decoder.name(), "Uniswap Universal Router");
+        assert!(registry.get(&UNIVERSAL_ROUTER, &hex!("deadbeef")).is_none());
+    }
+
+    #[test]
+    fn register_rejects_taken_pairs() {
+        let mut registry = DecoderRegistry::default();
+        let stub = Stub {
+            address: UNIVERSAL_ROUTER,
+            selectors: vec![hex!("deadbeef"), hex!("3593564c")],
+        };
+        let err = registry.register(Box::new(stub)).unwrap_err();
+        assert_eq!(err.decoder, "stub");
+        assert_eq!(
+            err.conflicts,
+            vec![(
+                UNIVERSAL_ROUTER,
+                hex!("3593564c"),
+                "Uniswap Universal Router".to_string()
+            )]
+        );
+        // nothing of the rejected decoder is registered
+        assert!(registry.get(&UNIVERSAL_ROUTER, &hex!("deadbeef")).is_none());
+    }
+
+    #[test]
+    fn register_accepts_free_pairs() {
+        let mut registry = DecoderRegistry::new();
+        let stub = Stub {
+            address: UNIVERSAL_ROUTER,
+            selectors: vec![hex!("deadbeef")],
+        };
+        registry.register(Box::new(stub)).unwrap();
+        let decoder = registry.get(&UNIVERSAL_ROUTER, &hex!("deadbeef")).unwrap();
+        assert_eq!(decoder.name(), "stub");
+    }
+
+    #[test]
+    fn one_decoder_per_address() {
+        let registry = DecoderRegistry::default();
+        let name = |to: &Address, selector: &[u8; 4]| registry.get(to, selector).map(|d| d.name());
+        for selector in DecoderOpenOcean::new().supported_selectors() {
+            assert_eq!(
+                name(&OPENOCEAN_EXCHANGE, &selector).as_deref(),
+                Some("OpenOcean Exchange")
+            );
+            assert!(name(&ZEROEX_PROXY, &selector).is_none());
+        }
+        for selector in DecoderZeroExProxy::new().supported_selectors() {
+            assert_eq!(
+                name(&ZEROEX_PROXY, &selector).as_deref(),
+                Some("ZeroExProxy")
+            );
+            assert!(name(&OPENOCEAN_EXCHANGE, &selector).is_none());
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use alloy::{
+    primitives::Address,
+    providers::network::TransactionResponse,
+    sol,
+    sol_types::{SolCall, SolInterface},
+};
+
+use super::{
+    universal_router::{net_swap, v2_decode_swap, SwapType},
+    DecodeError, Decoder, DecoderContext, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "Uniswap V2 Router02";
+    pub const ROUTER: Address = address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+}
+
+sol! {
+
+// cast interface 0x7a250d5630b4cf539739df2c5dacb4c659f2488d
+interface UniswapV2Router02 {
+    function swapETHForExactTokens(uint256 amountOut, address[] calldata path, address to, uint256 deadline)
+        external
+        payable
+        returns (uint256[] memory amounts);
+    function swapExactETHForTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline)
+        external
+        payable
+        returns (uint256[] memory amounts);
+    function swapExactETHForTokensSupportingFeeOnTransferTokens(
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external payable;
+    function swapExactTokensForETH(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external returns (uint256[] memory amounts);
+    function swapExactTokensForETHSupportingFeeOnTransferTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external;
+    function swapExactTokensForTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external returns (uint256[] memory amounts);
+    function swapExactTokensForTokensSupportingFeeOnTransferTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external;
+    function swapTokensForExactETH(
+        uint256 amountOut,
+        uint256 amountInMax,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external returns (uint256[] memory amounts);
+    function swapTokensForExactTokens(
+        uint256 amountOut,
+        uint256 amountInMax,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external returns (uint256[] memory amounts);
+}
+
+}
+
+pub struct DecoderUniswapV2 {}
+
+impl DecoderUniswapV2 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderUniswapV2 {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use UniswapV2Router02 as C;
+        vec![
+            C::swapETHForExactTokensCall::SELECTOR,
+            C::swapExactETHForTokensCall::SELECTOR,
+            C::swapExactETHForTokensSupportingFeeOnTransferTokensCall::SELECTOR,
+            C::swapExactTokensForETHCall::SELECTOR,
+            C::swapExactTokensForETHSupportingFeeOnTransferTokensCall::SELECTOR,
+            C::swapExactTokensForTokensCall::SELECTOR,
+            C::swapExactTokensForTokensSupportingFeeOnTransferTokensCall::SELECTOR,
+            C::swapTokensForExactETHCall::SELECTOR,
+            C::swapTokensForExactTokensCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use UniswapV2Router02::UniswapV2Router02Calls as C;
+        let tx = context.tx();
+
+        // the ETH variants wrap before the first pair or unwrap after the
+        // last one, their path has WETH in place of ETH
+        let (swap_type, path, to) = match C::abi_decode(&tx.input, true)? {
+            C::swapExactTokensForTokens(call) => (SwapType::ExactIn, call.path, call.to),
+            C::swapExactETHForTokens(call) => (SwapType::ExactIn, call.path, call.to),
+            C::swapExactTokensForETH(call) => (SwapType::ExactIn, call.path, call.to),
+            C::swapTokensForExactTokens(call) => (SwapType::ExactOut, call.path, call.to),
+            C::swapETHForExactTokens(call) => (SwapType::ExactOut, call.path, call.to),
+            C::swapTokensForExactETH(call) => (SwapType::ExactOut, call.path, call.to),
+            // the amounts in the pairs' logs are what reached them, what the
+            // user paid and got is recorded as a mismatch when it differs
+            C::swapExactTokensForTokensSupportingFeeOnTransferTokens(call) => {
+                (SwapType::ExactIn, call.path, call.to)
+            }
+            C::swapExactETHForTokensSupportingFeeOnTransferTokens(call) => {
+                (SwapType::ExactIn, call.path, call.to)
+            }
+            C::swapExactTokensForETHSupportingFeeOnTransferTokens(call) => {
+                (SwapType::ExactIn, call.path, call.to)
+            }
+        };
+        let swap = v2_decode_swap(&consts::ROUTER, swap_type, &path, context.logs()?)?;
+        let mut swap = match net_swap(tx.from(), to, vec![swap]) {
+            Some(swap) => swap,
+            None => return Ok(vec![]),
+        };
+        context.balance_deltas()?.settle(&mut swap);
+        Ok(vec![swap])
+    }
+}
@@ -1,8 +1,30 @@
 use alloy::{
-    primitives::address, rpc::types::{Transaction, TransactionReceipt}, sol, sol_types::{SolCall, SolInterface}
+    primitives::{Address, Bytes, LogData},
+    providers::network::TransactionResponse,
+    rpc::types::Log,
+    sol,
+    sol_types::{SolCall, SolInterface},
 };
 
-use super::{get_tx_receipt, get_tx_trace, Decoder};
+use super::{
+    balance::consts::ETH,
+    universal_router::{
+        consts::{ADDRESS_THIS, MSG_SENDER},
+        net_swap, v2_decode_swap, v3_compute_pool_address, v3_decode_leg, v3_decode_swap, Pool,
+        SwapEntry, SwapType,
+    },
+    DecodeError, Decoder, DecoderContext, Fee, Swap,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "Uniswap SwapRouter02";
+    pub const ROUTER: Address = address!("68b3465833fb72A70ecDF485E0e4C7bD8665Fc45");
+
+    pub const SWAP_ROUTER_NAME: &str = "Uniswap V3 SwapRouter";
+    pub const SWAP_ROUTER: Address = address!("E592427A0AEce92De3Edee1F18E0157C05861564");
+}
 
 sol! {
 
@@ -136,16 +158,102 @@ interface SwapRouter02 {
 
 }
 
+sol! {
+
+// cast interface 0xE592427A0AEce92De3Edee1F18E0157C05861564
+interface SwapRouter {
+    struct ExactInputParams {
+        bytes path;
+        address recipient;
+        uint256 deadline;
+        uint256 amountIn;
+        uint256 amountOutMinimum;
+    }
+
+    struct ExactInputSingleParams {
+        address tokenIn;
+        address tokenOut;
+        uint24 fee;
+        address recipient;
+        uint256 deadline;
+        uint256 amountIn;
+        uint256 amountOutMinimum;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    struct ExactOutputParams {
+        bytes path;
+        address recipient;
+        uint256 deadline;
+        uint256 amountOut;
+        uint256 amountInMaximum;
+    }
+
+    struct ExactOutputSingleParams {
+        address tokenIn;
+        address tokenOut;
+        uint24 fee;
+        address recipient;
+        uint256 deadline;
+        uint256 amountOut;
+        uint256 amountInMaximum;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    receive() external payable;
+
+    function WETH9() external view returns (address);
+    function exactInput(ExactInputParams memory params) external payable returns (uint256 amountOut);
+    function exactInputSingle(ExactInputSingleParams memory params) external payable returns (uint256 amountOut);
+    function exactOutput(ExactOutputParams memory params) external payable returns (uint256 amountIn);
+    function exactOutputSingle(ExactOutputSingleParams memory params) external payable returns (uint256 amountIn);
+    function factory() external view returns (address);
+    function multicall(bytes[] memory data) external payable returns (bytes[] memory results);
+    function refundETH() external payable;
+    function selfPermit(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s)
+        external
+        payable;
+    function selfPermitAllowed(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s)
+        external
+        payable;
+    function selfPermitAllowedIfNecessary(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s)
+        external
+        payable;
+    function selfPermitIfNecessary(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s)
+        external
+        payable;
+    function sweepToken(address token, uint256 amountMinimum, address recipient) external payable;
+    function sweepTokenWithFee(
+        address token,
+        uint256 amountMinimum,
+        address recipient,
+        uint256 feeBips,
+        address feeRecipient
+    ) external payable;
+    function uniswapV3SwapCallback(int256 amount0Delta, int256 amount1Delta, bytes memory _data) external;
+    function unwrapWETH9(uint256 amountMinimum, address recipient) external payable;
+    function unwrapWETH9WithFee(uint256 amountMinimum, address recipient, uint256 feeBips, address feeRecipient)
+        external
+        payable;
+}
+
+}
+
 pub struct DecoderUniswapV3 {}
 
+impl DecoderUniswapV3 {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
 impl Decoder for DecoderUniswapV3 {
     fn name(&self) -> String {
-        "UniswapV3".to_string()
+        consts::NAME.to_string()
     }
 
-    fn supported_address(&self) -> Vec<alloy::primitives::Address> {
-        // vec![address!("")]
-        todo!()
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::ROUTER]
     }
 
     fn supported_selectors(&self) -> Vec<[u8; 4]> {
@@ -158,30 +266,260 @@ impl Decoder for DecoderUniswapV3 {
             C::multicall_0Call::SELECTOR,
             C::multicall_1Call::SELECTOR,
             C::multicall_2Call::SELECTOR,
+            C::swapExactTokensForTokensCall::SELECTOR,
+            C::swapTokensForExactTokensCall::SELECTOR,
         ]
     }
 
-    fn decode(&self, context: &super::DecoderContext) -> eyre::Result<super::Swap> {
-        todo!()
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let mut calls = Calls::new(consts::ROUTER);
+        calls.add(&context.tx().input, context.logs()?)?;
+        calls.into_swaps(context)
+    }
+}
+
+/// The original V3 router, whose params carry a `deadline` and whose
+/// periphery calls take explicit recipients.
+pub struct DecoderUniswapV3SwapRouter {}
+
+impl DecoderUniswapV3SwapRouter {
+    pub fn new() -> Self {
+        Self {}
     }
 }
 
-// pub fn get_swap_info(tx: Transaction) -> eyre::Result<()> {
-//     use SwapRouter02::SwapRouter02Calls as C;
-
-//     let call_dispatch = C::abi_decode(&tx.input, true)?;
-//     let receipt = futures::executor::block_on(get_tx_receipt(url, &tx.hash))?;
-//     let trace = futures::executor::block_on(get_tx_trace(url, &tx.hash))?;
-
-//     match call_dispatch {
-//         C::exactInput(call) => {}
-//         C::exactInputSingle(_) => todo!(),
-//         C::exactOutput(_) => todo!(),
-//         C::exactOutputSingle(_) => todo!(),
-//         C::multicall_0(_) => todo!(),
-//         C::multicall_1(_) => todo!(),
-//         C::multicall_2(_) => todo!(),
-//         _ => unimplemented!("Uniswap V3"),
-//     }
-//     Ok(())
-// }
+impl Decoder for DecoderUniswapV3SwapRouter {
+    fn name(&self) -> String {
+        consts::SWAP_ROUTER_NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::SWAP_ROUTER]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        use SwapRouter as C;
+        vec![
+            C::exactInputCall::SELECTOR,
+            C::exactInputSingleCall::SELECTOR,
+            C::exactOutputCall::SELECTOR,
+            C::exactOutputSingleCall::SELECTOR,
+            C::multicallCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let mut calls = Calls::new(consts::SWAP_ROUTER);
+        calls.add_v1(&context.tx().input, context.logs()?)?;
+        calls.into_swaps(context)
+    }
+}
+
+/// The calls of a (possibly nested) `multicall`, in execution order.
+struct Calls {
+    router: Address,
+    /// every swap along with its unresolved recipient
+    swaps: Vec<(Address, SwapEntry)>,
+    /// where `unwrapWETH9*` or `sweepToken*` forward the router's balance
+    sweep_recipient: Option<Address>,
+    /// (recipient, token) of the `*WithFee` calls
+    fees: Vec<(Address, Address)>,
+}
+
+impl Calls {
+    fn new(router: Address) -> Self {
+        Self {
+            router,
+            swaps: vec![],
+            sweep_recipient: None,
+            fees: vec![],
+        }
+    }
+
+    /// Combines the swaps into the user's net swap, sent to wherever the
+    /// router forwarded the last swap's output.
+    fn into_swaps(self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let msg_sender = context.tx().from();
+        let resolve = |recipient: Address| match recipient {
+            MSG_SENDER => msg_sender,
+            // the original router reads address(0) as itself
+            ADDRESS_THIS | Address::ZERO => self.router,
+            _ => recipient,
+        };
+        let recipient = match (self.swaps.last(), self.sweep_recipient) {
+            (Some((recipient, _)), Some(sweep_recipient)) if resolve(*recipient) == self.router => {
+                sweep_recipient
+            }
+            (Some((recipient, _)), _) => *recipient,
+            (None, _) => return Ok(vec![]),
+        };
+        let swaps = self.swaps.into_iter().map(|(_, swap)| swap).collect();
+        let mut swap = match net_swap(msg_sender, resolve(recipient), swaps) {
+            Some(swap) => swap,
+            None => return Ok(vec![]),
+        };
+        let deltas = context.balance_deltas()?;
+        deltas.settle(&mut swap);
+        swap.fees = self
+            .fees
+            .into_iter()
+            .map(|(recipient, token)| Fee {
+                recipient,
+                token,
+                amount: deltas.received(&recipient, &token),
+            })
+            .filter(|fee| !fee.amount.is_zero())
+            .collect();
+        Ok(vec![swap])
+    }
+
+    fn add(&mut self, input: &[u8], logs: &[Log<LogData>]) -> Result<(), DecodeError> {
+        use SwapRouter02::SwapRouter02Calls as C;
+        let router = &self.router;
+
+        match C::abi_decode(input, true)? {
+            C::multicall_0(call) => return self.add_all(&call.data, logs),
+            C::multicall_1(call) => return self.add_all(&call.data, logs),
+            C::multicall_2(call) => return self.add_all(&call.data, logs),
+
+            C::exactInput(call) => {
+                let params = call.params;
+                let swap = v3_decode_swap(router, SwapType::ExactIn, &params.path, logs)?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactOutput(call) => {
+                let params = call.params;
+                let swap = v3_decode_swap(router, SwapType::ExactOut, &params.path, logs)?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactInputSingle(call) => {
+                let params = call.params;
+                let pool = v3_pool(params.tokenIn, params.tokenOut, params.fee)?;
+                let swap = SwapEntry::from_legs(vec![v3_decode_leg(router, &pool, logs)?])?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactOutputSingle(call) => {
+                let params = call.params;
+                let pool = v3_pool(params.tokenIn, params.tokenOut, params.fee)?;
+                let swap = SwapEntry::from_legs(vec![v3_decode_leg(router, &pool, logs)?])?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::swapExactTokensForTokens(call) => {
+                let swap = v2_decode_swap(router, SwapType::ExactIn, &call.path, logs)?;
+                self.swaps.push((call.to, swap));
+            }
+            C::swapTokensForExactTokens(call) => {
+                let swap = v2_decode_swap(router, SwapType::ExactOut, &call.path, logs)?;
+                self.swaps.push((call.to, swap));
+            }
+
+            C::unwrapWETH9_0(call) => self.sweep_recipient = Some(call.recipient),
+            C::unwrapWETH9_1(_) => self.sweep_recipient = Some(MSG_SENDER),
+            C::unwrapWETH9WithFee_0(call) => {
+                self.sweep_recipient = Some(call.recipient);
+                self.fees.push((call.feeRecipient, ETH));
+            }
+            C::unwrapWETH9WithFee_1(call) => {
+                self.sweep_recipient = Some(MSG_SENDER);
+                self.fees.push((call.feeRecipient, ETH));
+            }
+            C::sweepToken_0(call) => self.sweep_recipient = Some(call.recipient),
+            C::sweepToken_1(_) => self.sweep_recipient = Some(MSG_SENDER),
+            C::sweepTokenWithFee_0(call) => {
+                self.sweep_recipient = Some(MSG_SENDER);
+                self.fees.push((call.feeRecipient, call.token));
+            }
+            C::sweepTokenWithFee_1(call) => {
+                self.sweep_recipient = Some(call.recipient);
+                self.fees.push((call.feeRecipient, call.token));
+            }
+
+            // no tokens change hands besides the user's input
+            C::refundETH(_)
+            | C::wrapETH(_)
+            | C::pull(_)
+            | C::selfPermit(_)
+            | C::selfPermitAllowed(_)
+            | C::selfPermitAllowedIfNecessary(_)
+            | C::selfPermitIfNecessary(_)
+            | C::approveMax(_)
+            | C::approveMaxMinusOne(_)
+            | C::approveZeroThenMax(_)
+            | C::approveZeroThenMaxMinusOne(_)
+            | C::checkOracleSlippage_0(_)
+            | C::checkOracleSlippage_1(_) => {}
+            call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+        }
+        Ok(())
+    }
+
+    fn add_v1(&mut self, input: &[u8], logs: &[Log<LogData>]) -> Result<(), DecodeError> {
+        use SwapRouter::SwapRouterCalls as C;
+        let router = &self.router;
+
+        match C::abi_decode(input, true)? {
+            C::multicall(call) => {
+                return call
+                    .data
+                    .iter()
+                    .try_for_each(|input| self.add_v1(input, logs))
+            }
+
+            C::exactInput(call) => {
+                let params = call.params;
+                let swap = v3_decode_swap(router, SwapType::ExactIn, &params.path, logs)?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactOutput(call) => {
+                let params = call.params;
+                let swap = v3_decode_swap(router, SwapType::ExactOut, &params.path, logs)?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactInputSingle(call) => {
+                let params = call.params;
+                let pool = v3_pool(params.tokenIn, params.tokenOut, params.fee)?;
+                let swap = SwapEntry::from_legs(vec![v3_decode_leg(router, &pool, logs)?])?;
+                self.swaps.push((params.recipient, swap));
+            }
+            C::exactOutputSingle(call) => {
+                let params = call.params;
+                let pool = v3_pool(params.tokenIn, params.tokenOut, params.fee)?;
+                let swap = SwapEntry::from_legs(vec![v3_decode_leg(router, &pool, logs)?])?;
+                self.swaps.push((params.recipient, swap));
+            }
+
+            C::unwrapWETH9(call) => self.sweep_recipient = Some(call.recipient),
+            C::unwrapWETH9WithFee(call) => {
+                self.sweep_recipient = Some(call.recipient);
+                self.fees.push((call.feeRecipient, ETH));
+            }
+            C::sweepToken(call) => self.sweep_recipient = Some(call.recipient),
+            C::sweepTokenWithFee(call) => {
+                self.sweep_recipient = Some(call.recipient);
+                self.fees.push((call.feeRecipient, call.token));
+            }
+
+            C::refundETH(_)
+            | C::selfPermit(_)
+            | C::selfPermitAllowed(_)
+            | C::selfPermitAllowedIfNecessary(_)
+            | C::selfPermitIfNecessary(_) => {}
+            call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+        }
+        Ok(())
+    }
+
+    fn add_all(&mut self, data: &[Bytes], logs: &[Log<LogData>]) -> Result<(), DecodeError> {
+        data.iter().try_for_each(|input| self.add(input, logs))
+    }
+}
+
+fn v3_pool(token_in: Address, token_out: Address, fee: u32) -> Result<Pool, DecodeError> {
+    Ok(Pool {
+        token_in,
+        token_out,
+        fee,
+        pool: v3_compute_pool_address(token_in, token_out, fee, None, None)?,
+        reverse: token_in > token_out,
+    })
+}
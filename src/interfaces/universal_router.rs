@@ -6,16 +6,15 @@ use alloy::{
     },
     providers::network::TransactionResponse,
     rpc::types::{
-        trace::parity::{Action, CallType, TraceOutput, TransactionTrace},
+        trace::parity::{Action, CallAction, CallType, TraceOutput, TransactionTrace},
         Log, Transaction,
     },
     sol,
     sol_types::{SolCall, SolEvent, SolInterface, SolType, SolValue},
 };
-use eyre::eyre;
 use serde::{Deserialize, Serialize};
 
-use super::Decoder;
+use super::{balance, DecodeError, Decoder, DecoderContext, NftPurchase, Protocol, SwapLeg};
 
 pub mod consts {
     use alloy::primitives::{address, b256, Address, B256};
@@ -91,9 +90,33 @@ interface Dispatcher {
     );
 }
 
+interface CryptoPunksMarket {
+    function buyPunk(uint punkIndex) external payable;
+}
+
+// ERC-721 and ERC-1155 transfers
+interface NonFungible {
+    event Transfer(address indexed from, address indexed to, uint256 indexed tokenId);
+
+    function transferFrom(address from, address to, uint256 tokenId) external;
+    function safeTransferFrom(address from, address to, uint256 tokenId) external;
+    function safeTransferFrom(address from, address to, uint256 tokenId, bytes data) external;
+    function safeTransferFrom(address from, address to, uint256 id, uint256 amount, bytes data) external;
+    function safeBatchTransferFrom(
+        address from,
+        address to,
+        uint256[] ids,
+        uint256[] amounts,
+        bytes data
+    ) external;
+}
+
 }
 
 mod command_types {
+    // Masks to extract certain bits of commands
+    pub const FLAG_ALLOW_REVERT: u8 = 0x80;
+
     // Command Types where value<0x08, executed in the first nested-if block
     pub const V3_SWAP_EXACT_IN: u8 = 0x00;
     pub const V3_SWAP_EXACT_OUT: u8 = 0x01;
@@ -114,25 +137,100 @@ mod command_types {
     pub const COMMAND_PLACEHOLDER_0x0e: u8 = 0x0e;
     pub const COMMAND_PLACEHOLDER_0x0f: u8 = 0x0f;
 
-    // Command Types where 0x10<=value<0x18, executed in the third nested-if block
+    // Command Types where 0x10<=value<0x18, executed in the third nested-if
+    // block; 0x11 and 0x14 differ between versions
     pub const SEAPORT: u8 = 0x10;
-    pub const LOOKS_RARE_721: u8 = 0x11;
     pub const NFTX: u8 = 0x12;
     pub const CRYPTOPUNKS: u8 = 0x13;
-    pub const LOOKS_RARE_1155: u8 = 0x14;
     pub const OWNER_CHECK_721: u8 = 0x15;
     pub const OWNER_CHECK_1155: u8 = 0x16;
     pub const SWEEP_ERC721: u8 = 0x17;
 
-    // Command Types where 0x18<=value<=0x1f, executed in the final nested-if block
+    // Command Types where 0x18<=value<=0x1f, executed in the final nested-if
+    // block; 0x1e differs between versions
     pub const X2Y2_721: u8 = 0x18;
     pub const SUDOSWAP: u8 = 0x19;
     pub const NFT20: u8 = 0x1a;
     pub const X2Y2_1155: u8 = 0x1b;
     pub const FOUNDATION: u8 = 0x1c;
     pub const SWEEP_ERC1155: u8 = 0x1d;
-    pub const COMMAND_PLACEHOLDER_0x1e: u8 = 0x1e;
     pub const COMMAND_PLACEHOLDER_0x1f: u8 = 0x1f;
+
+    /// Universal Router 1.0, at `consts::ROUTER_V2`
+    pub mod v1 {
+        pub const COMMAND_TYPE_MASK: u8 = 0x1f;
+
+        pub const LOOKS_RARE_721: u8 = 0x11;
+        pub const LOOKS_RARE_1155: u8 = 0x14;
+        pub const COMMAND_PLACEHOLDER_0x1e: u8 = 0x1e;
+    }
+
+    /// Universal Router 1.2, at `consts::ROUTER`
+    pub mod v1_2 {
+        pub const COMMAND_TYPE_MASK: u8 = 0x3f;
+
+        // `SEAPORT` is Seaport 1.5 here
+        pub const LOOKS_RARE_V2: u8 = 0x11;
+        pub const COMMAND_PLACEHOLDER_0x14: u8 = 0x14;
+        pub const ELEMENT_MARKET: u8 = 0x1e;
+
+        // Command Types where 0x20<=value
+        pub const SEAPORT_V1_4: u8 = 0x20;
+        pub const EXECUTE_SUB_PLAN: u8 = 0x21;
+        pub const APPROVE_ERC20: u8 = 0x22;
+    }
+}
+
+/// How an NFT command's input is laid out, which differs per router version
+/// for the same command byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NftCommand {
+    /// `(uint256 value, bytes data)`, `data` being the marketplace calldata
+    Market,
+    /// `(uint256 value, bytes data, address recipient, address token, uint256 id)`
+    Market721,
+    /// `Market721` followed by `uint256 amount`
+    Market1155,
+    /// `(uint256 punkId, address recipient, uint256 value)`
+    CryptoPunks,
+    /// `(address token, address recipient, uint256 id)`
+    Sweep721,
+    /// `(address token, address recipient, uint256 id, uint256 amount)`
+    Sweep1155,
+    /// ownership checks, which make no call
+    OwnerCheck,
+}
+
+/// The command type of `command` on `router`, flags masked off.
+fn command_type(router: &Address, command: u8) -> u8 {
+    match *router {
+        consts::ROUTER_V2 => command & command_types::v1::COMMAND_TYPE_MASK,
+        _ => command & command_types::v1_2::COMMAND_TYPE_MASK,
+    }
+}
+
+/// The NFT command a command type stands for on `router`, if any.
+fn nft_command(router: &Address, command_type: u8) -> Option<NftCommand> {
+    use command_types::*;
+    let command = match command_type {
+        SEAPORT | NFTX | SUDOSWAP | NFT20 => NftCommand::Market,
+        X2Y2_721 | FOUNDATION => NftCommand::Market721,
+        X2Y2_1155 => NftCommand::Market1155,
+        CRYPTOPUNKS => NftCommand::CryptoPunks,
+        SWEEP_ERC721 => NftCommand::Sweep721,
+        SWEEP_ERC1155 => NftCommand::Sweep1155,
+        OWNER_CHECK_721 | OWNER_CHECK_1155 => NftCommand::OwnerCheck,
+        command_type if *router == consts::ROUTER_V2 => match command_type {
+            v1::LOOKS_RARE_721 => NftCommand::Market721,
+            v1::LOOKS_RARE_1155 => NftCommand::Market1155,
+            _ => return None,
+        },
+        command_type => match command_type {
+            v1_2::LOOKS_RARE_V2 | v1_2::ELEMENT_MARKET | v1_2::SEAPORT_V1_4 => NftCommand::Market,
+            _ => return None,
+        },
+    };
+    Some(command)
 }
 
 pub struct DecoderUnivesalRouter {}
@@ -157,7 +255,7 @@ impl Decoder for DecoderUnivesalRouter {
         vec![C::execute_0Call::SELECTOR, C::execute_1Call::SELECTOR]
     }
 
-    fn decode(&self, context: &super::DecoderContext) -> eyre::Result<super::Swap> {
+    fn decode(&self, context: &super::DecoderContext) -> Result<Vec<super::Swap>, DecodeError> {
         use UniversalRouter::UniversalRouterCalls as C;
         let msg_sender = context.tx().from();
         let router = context.tx().to().unwrap();
@@ -166,7 +264,11 @@ impl Decoder for DecoderUnivesalRouter {
             C::execute_0(call) => (call.commands, call.inputs),
             C::execute_1(call) => (call.commands, call.inputs),
         };
+        let mut swaps = vec![];
+        // output held by the router, forwarded later by SWEEP or UNWRAP_WETH
+        let mut sweep_recipient = None;
         for (index, command) in commands.into_iter().enumerate() {
+            let command = command_type(&router, command);
             match command {
                 // Swap operations
                 // For swap operations, payer is either msg.sender or address(this)
@@ -175,53 +277,32 @@ impl Decoder for DecoderUnivesalRouter {
                     let (recipient, amount_in, _amount_out_min, path, _payer_is_user) =
                         Params::abi_decode_params(&inputs[index], true)?;
 
-                    let swap = v3_decode_swap(
-                        &router,
-                        SwapType::ExactIn(amount_in),
-                        &path,
-                        &context.logs()?,
-                    )?;
-                    println!("V3_SWAP_EXACT_IN: {:?}", swap);
+                    let swap = v3_decode_swap(&router, SwapType::ExactIn, &path, context.logs()?)?;
+                    swaps.push((recipient, swap));
                 }
                 command_types::V3_SWAP_EXACT_OUT => {
                     type Params = sol!((address, uint256, uint256, bytes, bool));
                     let (recipient, amount_out, _amount_in_max, path, _payer_is_user) =
                         Params::abi_decode_params(&inputs[index], true)?;
 
-                    let swap = v3_decode_swap(
-                        &router,
-                        SwapType::ExactOut(amount_out),
-                        &path,
-                        &context.logs()?,
-                    )?;
-                    println!("V3_SWAP_EXACT_OUT: {:?}", swap);
+                    let swap = v3_decode_swap(&router, SwapType::ExactOut, &path, context.logs()?)?;
+                    swaps.push((recipient, swap));
                 }
                 command_types::V2_SWAP_EXACT_IN => {
                     type Params = sol!((address, uint256, uint256, address[], bool));
                     let (recipient, amount_in, amount_out_min, path, payer_is_user) =
                         Params::abi_decode_params(&inputs[index], true)?;
 
-                    let swap = v2_decode_swap(
-                        &router,
-                        SwapType::ExactIn(amount_in),
-                        &path,
-                        &context.logs()?,
-                    )?;
-                    println!("V2_SWAP_EXACT_IN: {:?}", swap);
-                    // let swap = v2_decode_swap(&path, &context.trace().trace)?;
+                    let swap = v2_decode_swap(&router, SwapType::ExactIn, &path, context.logs()?)?;
+                    swaps.push((recipient, swap));
                 }
                 command_types::V2_SWAP_EXACT_OUT => {
                     type Params = sol!((address, uint256, uint256, address[], bool));
                     let (recipient, amount_out, amount_in_max, path, payer_is_user) =
                         Params::abi_decode_params(&inputs[index], true)?;
 
-                    let swap = v2_decode_swap(
-                        &router,
-                        SwapType::ExactOut(amount_out),
-                        &path,
-                        &context.logs()?,
-                    )?;
-                    println!("V2_SWAP_EXACT_OUT: {:?}", swap);
+                    let swap = v2_decode_swap(&router, SwapType::ExactOut, &path, context.logs()?)?;
+                    swaps.push((recipient, swap));
                 }
 
                 // non-swap commands: 0x00 <= command < 0x08
@@ -231,24 +312,16 @@ impl Decoder for DecoderUnivesalRouter {
                     type Params = sol!((address, address, uint256));
                     let (token, recipient, amount_min) =
                         Params::abi_decode_params(&inputs[index], true)?;
-                    if recipient != consts::MSG_SENDER {
-                        println!("SWEEP: {:?} {:?}", recipient, token);
-                    }
+                    sweep_recipient = Some(recipient);
                 }
                 command_types::TRANSFER => {
                     type Params = sol!((address, address, uint256));
                     let (token, recipient, value) =
                         Params::abi_decode_params(&inputs[index], true)?;
-                    if recipient != consts::MSG_SENDER && recipient != consts::FEE_COLLECTOR {
-                        println!("TRANSFER: {:?} {:?} {:?}", token, recipient, value);
-                    }
                 }
                 command_types::PAY_PORTION => {
                     type Params = sol!((address, address, uint256));
                     let (token, recipient, bips) = Params::abi_decode_params(&inputs[index], true)?;
-                    if recipient != consts::MSG_SENDER && recipient != consts::FEE_COLLECTOR {
-                        println!("PAY_PORTION: {:?} {:?} {:?}", token, recipient, bips);
-                    }
                 }
                 // 0x08 <= command < 0x10
                 command_types::PERMIT2_PERMIT => {
@@ -260,173 +333,573 @@ impl Decoder for DecoderUnivesalRouter {
                 command_types::WRAP_ETH => {
                     type Params = sol!((address, uint256));
                     let (recipient, amount_min) = Params::abi_decode_params(&inputs[index], true)?;
-                    if recipient != consts::ADDRESS_THIS {
-                        println!("WRAP_ETH: {:?}", recipient);
-                    }
                 }
                 command_types::UNWRAP_WETH => {
                     type Params = sol!((address, uint256));
                     let (recipient, amount_min) = Params::abi_decode_params(&inputs[index], true)?;
-                    if recipient != consts::MSG_SENDER {
-                        println!("UNWRAP_WETH: {:?}", recipient);
-                    }
+                    sweep_recipient = Some(recipient);
                 }
                 command_types::PERMIT2_TRANSFER_FROM_BATCH => {}
-                _ => return Err(eyre!("unsupported command: {}", command)),
+
+                command_types::v1_2::APPROVE_ERC20 => {}
+
+                // NFT commands, reported by `nft_purchases`
+                command if nft_command(&router, command).is_some() => {}
+                _ => return Err(DecodeError::UnsupportedCommand(command)),
             }
         }
-        todo!()
-    }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SwapEntry {
-    pools: Vec<Pool>,
-    token_in: Address,
-    token_out: Address,
-    amount_in: U256,
-    amount_out: U256,
-}
+        let resolve = |recipient: Address| match recipient {
+            consts::MSG_SENDER => msg_sender,
+            consts::ADDRESS_THIS => router,
+            _ => recipient,
+        };
+        let mut decoded = vec![];
+        for (recipient, swaps) in chain_swaps(swaps) {
+            let recipient = match (recipient, sweep_recipient) {
+                (consts::ADDRESS_THIS, Some(sweep_recipient)) => sweep_recipient,
+                (recipient, _) => recipient,
+            };
+            if let Some(mut swap) = net_swap(msg_sender, resolve(recipient), swaps) {
+                context.balance_deltas()?.settle(&mut swap);
+                decoded.push(swap);
+            }
+        }
+        Ok(decoded)
+    }
 
-enum SwapType {
-    ExactIn(U256),
-    ExactOut(U256),
-}
+    fn nft_purchases(&self, context: &DecoderContext) -> Result<Vec<NftPurchase>, DecodeError> {
+        use UniversalRouter::UniversalRouterCalls as C;
+        let router = context.tx().to().unwrap();
 
-fn v3_decode_swap(
-    router: &Address,
-    swap_type: SwapType,
-    path: &Bytes,
-    logs: &[Log<LogData>],
-) -> eyre::Result<SwapEntry> {
-    let analyze_swap = |pool: &Address| -> eyre::Result<U256> {
-        let swap_logs = logs
+        let (commands, inputs) = match C::abi_decode(&context.tx().input, true)? {
+            C::execute_0(call) => (call.commands, call.inputs),
+            C::execute_1(call) => (call.commands, call.inputs),
+        };
+        let trace = &context.trace()?.trace;
+        let logs = context.logs()?;
+        // the router's own calls, in execution order
+        let calls = trace
             .iter()
-            .filter(|log| log.address() == *pool && !log.removed)
-            .filter_map(|log| {
-                let swap = Dispatcher::Swap_0::decode_log(&log.inner, true).ok()?;
-                match swap.sender == *router {
-                    true => Some((swap.amount0, swap.amount1)),
-                    false => None,
+            .filter(|trace| trace.trace_address.len() == 1)
+            .filter_map(|trace| match &trace.action {
+                Action::Call(call) if call.from == router && call.call_type == CallType::Call => {
+                    Some((trace, call))
                 }
+                _ => None,
             })
             .collect::<Vec<_>>();
-        if swap_logs.len() != 1 {
-            return Err(eyre!("multiple pool calls not supported"));
+        // calls before `next` belong to earlier commands
+        let mut next = 0;
+        let mut purchases: Vec<NftPurchase> = vec![];
+        // NFTs left in the router, forwarded by SWEEP_ERC721 or SWEEP_ERC1155
+        let mut sweeps = vec![];
+        for (index, command) in commands.into_iter().enumerate() {
+            let command = match nft_command(&router, command_type(&router, command)) {
+                Some(command) => command,
+                None => continue,
+            };
+            // (value, calldata sent to the marketplace, (recipient,
+            // collection, id)) when the command says what it buys, `None` as
+            // collection for the marketplace itself
+            let (value, data, item) = match command {
+                NftCommand::Market => {
+                    type Params = sol!((uint256, bytes));
+                    let (value, data) = Params::abi_decode_params(&inputs[index], true)?;
+                    (value, data, None)
+                }
+                NftCommand::Market721 => {
+                    type Params = sol!((uint256, bytes, address, address, uint256));
+                    let (value, data, recipient, token, id) =
+                        Params::abi_decode_params(&inputs[index], true)?;
+                    (value, data, Some((recipient, Some(token), id)))
+                }
+                NftCommand::Market1155 => {
+                    type Params = sol!((uint256, bytes, address, address, uint256, uint256));
+                    let (value, data, recipient, token, id, _amount) =
+                        Params::abi_decode_params(&inputs[index], true)?;
+                    (value, data, Some((recipient, Some(token), id)))
+                }
+                NftCommand::CryptoPunks => {
+                    type Params = sol!((uint256, address, uint256));
+                    let (punk_id, recipient, value) =
+                        Params::abi_decode_params(&inputs[index], true)?;
+                    let data = CryptoPunksMarket::buyPunkCall { punkIndex: punk_id }.abi_encode();
+                    (value, data.into(), Some((recipient, None, punk_id)))
+                }
+                NftCommand::Sweep721 => {
+                    type Params = sol!((address, address, uint256));
+                    let (token, recipient, _id) = Params::abi_decode_params(&inputs[index], true)?;
+                    sweeps.push((token, recipient));
+                    continue;
+                }
+                NftCommand::Sweep1155 => {
+                    type Params = sol!((address, address, uint256, uint256));
+                    let (token, recipient, _id, _amount) =
+                        Params::abi_decode_params(&inputs[index], true)?;
+                    sweeps.push((token, recipient));
+                    continue;
+                }
+                NftCommand::OwnerCheck => continue,
+            };
+            // the router forwards `data` as is, calls made in between belong
+            // to the swap and transfer commands
+            let position = calls[next..]
+                .iter()
+                .position(|(_, call)| call.value == value && call.input == data);
+            let (frame, call) = match position {
+                Some(position) => {
+                    next += position + 1;
+                    calls[next - 1]
+                }
+                None => continue,
+            };
+            // a command flagged with FLAG_ALLOW_REVERT may have failed
+            if frame.error.is_some() {
+                continue;
+            }
+
+            let marketplace = call.to;
+            let items = match item {
+                Some((recipient, collection, id)) => {
+                    vec![(collection.unwrap_or(marketplace), recipient, id)]
+                }
+                None => nft_transfers(trace, frame, logs),
+            };
+            let (payment_token, mut payment_amount) =
+                frame_payment(trace, frame, logs, &router, value);
+            let first = purchases.len();
+            for (collection, recipient, id) in items {
+                let purchase = purchases[first..]
+                    .iter_mut()
+                    .find(|p| p.collection == collection && p.recipient == recipient);
+                match purchase {
+                    Some(purchase) => purchase.token_ids.push(id),
+                    None => purchases.push(NftPurchase {
+                        marketplace,
+                        collection,
+                        token_ids: vec![id],
+                        payment_token,
+                        payment_amount: std::mem::take(&mut payment_amount),
+                        recipient,
+                    }),
+                }
+            }
+        }
+
+        for purchase in purchases.iter_mut().filter(|p| p.recipient == router) {
+            if let Some((_, recipient)) = sweeps.iter().find(|(t, _)| *t == purchase.collection) {
+                purchase.recipient = *recipient;
+            }
         }
-        let (amount_0, amount_1) = swap_logs[0];
-        Ok(if amount_0.is_negative() {
-            amount_0.abs().into_raw()
-        } else {
-            amount_1.abs().into_raw()
+        Ok(purchases)
+    }
+}
+
+/// Successful calls made within `frame`, its own call excluded.
+pub(crate) fn frame_calls<'a>(
+    trace: &'a [TransactionTrace],
+    frame: &'a TransactionTrace,
+) -> impl Iterator<Item = &'a CallAction> + 'a {
+    let depth = frame.trace_address.len();
+    let reverted = trace
+        .iter()
+        .filter(|trace| trace.error.is_some())
+        .map(|trace| trace.trace_address.as_slice())
+        .collect::<Vec<_>>();
+    trace
+        .iter()
+        .filter(move |trace| {
+            trace.trace_address.len() > depth
+                && trace.trace_address.starts_with(&frame.trace_address)
+                && !reverted
+                    .iter()
+                    .any(|address| trace.trace_address.starts_with(address))
         })
-    };
+        .filter_map(|trace| match &trace.action {
+            Action::Call(call) if call.call_type == CallType::Call => Some(call),
+            _ => None,
+        })
+}
+
+/// The `visit`-th successful call to `pool` in the trace, in call order.
+pub(crate) fn pool_frame<'a>(
+    trace: &'a [TransactionTrace],
+    pool: &Address,
+    visit: usize,
+) -> Result<&'a TransactionTrace, DecodeError> {
+    trace
+        .iter()
+        .filter(|frame| {
+            frame.error.is_none()
+                && matches!(&frame.action, Action::Call(call) if call.to == *pool && call.call_type == CallType::Call)
+        })
+        .nth(visit)
+        .ok_or(DecodeError::UnresolvedToken(*pool))
+}
+
+/// Token `pool` paid out during its `visit`-th call in the trace, in call
+/// order: the token of the first ERC-20 `transfer` the pool made within that
+/// frame. Scoping to the frame keeps other visits of the pool, and unrelated
+/// transfers out of it elsewhere in the transaction, from being picked up.
+pub(crate) fn pool_token_out(
+    trace: &[TransactionTrace],
+    pool: &Address,
+    visit: usize,
+) -> Result<Address, DecodeError> {
+    trace
+        .iter()
+        .filter(|frame| {
+            frame.error.is_none()
+                && matches!(&frame.action, Action::Call(call) if call.to == *pool && call.call_type == CallType::Call)
+        })
+        .filter_map(|frame| {
+            frame_calls(trace, frame)
+                .find(|call| {
+                    call.from == *pool && balance::ERC20::transferCall::abi_decode(&call.input, true).is_ok()
+                })
+                .map(|call| call.to)
+        })
+        .nth(visit)
+        .ok_or(DecodeError::UnresolvedToken(*pool))
+}
+
+/// Token paid into `pool` during its `visit`-th call in the trace: the token
+/// of the first ERC-20 `transfer` or `transferFrom` to the pool made within
+/// that frame, which V3 pools collect in their swap callback.
+pub(crate) fn pool_token_in(
+    trace: &[TransactionTrace],
+    pool: &Address,
+    visit: usize,
+) -> Result<Address, DecodeError> {
+    use balance::ERC20::ERC20Calls as E;
+    trace
+        .iter()
+        .filter(|frame| {
+            frame.error.is_none()
+                && matches!(&frame.action, Action::Call(call) if call.to == *pool && call.call_type == CallType::Call)
+        })
+        .filter_map(|frame| {
+            frame_calls(trace, frame)
+                .find(|call| match E::abi_decode(&call.input, true) {
+                    Ok(E::transfer(transfer)) => transfer.to == *pool,
+                    Ok(E::transferFrom(transfer)) => transfer.to == *pool,
+                    Err(_) => false,
+                })
+                .map(|call| call.to)
+        })
+        .nth(visit)
+        .ok_or(DecodeError::UnresolvedToken(*pool))
+}
+
+/// True when `collection` logged an ERC-721 `Transfer` of `id`, which sets
+/// it apart from an ERC-20 `transferFrom` of `id` tokens.
+fn is_nft_transfer(logs: &[Log<LogData>], collection: &Address, id: &U256) -> bool {
+    logs.iter().any(|log| {
+        let topics = log.topics();
+        log.address() == *collection
+            && topics.len() == 4
+            && topics[0] == NonFungible::Transfer::SIGNATURE_HASH
+            && topics[3] == B256::from(*id)
+    })
+}
 
-    let pools = v3_decode_path(&path);
-    if pools.is_empty() {
-        return Err(eyre!("no path found"));
+/// What the router paid within a marketplace `frame`: the ETH it sent, less
+/// refunds, or else the ERC-20 tokens pulled from it.
+fn frame_payment(
+    trace: &[TransactionTrace],
+    frame: &TransactionTrace,
+    logs: &[Log<LogData>],
+    router: &Address,
+    value: U256,
+) -> (Address, U256) {
+    use NonFungible::NonFungibleCalls as N;
+    let refund = frame_calls(trace, frame)
+        .filter(|call| call.to == *router)
+        .map(|call| call.value)
+        .sum::<U256>();
+    if value > refund {
+        return (balance::consts::ETH, value - refund);
     }
-    let (token_in, token_out) = (
-        pools.first().unwrap().token_in,
-        pools.last().unwrap().token_out,
-    );
-    let (amount_in, amount_out) = match swap_type {
-        SwapType::ExactIn(amount_in) => {
-            let amount_out = analyze_swap(&pools.last().unwrap().pool)?;
-            (amount_in, amount_out)
+    let mut pulled = frame_calls(trace, frame).filter_map(|call| {
+        match N::abi_decode(&call.input, true).ok()? {
+            N::transferFrom(transfer)
+                if transfer.from == *router
+                    && !is_nft_transfer(logs, &call.to, &transfer.tokenId) =>
+            {
+                Some((call.to, transfer.tokenId))
+            }
+            _ => None,
         }
-        SwapType::ExactOut(amount_out) => {
-            let amount_in = analyze_swap(&pools.first().unwrap().pool)?;
-            (amount_in, amount_out)
+    });
+    match pulled.next() {
+        Some((token, amount)) => {
+            let rest = pulled
+                .filter(|(t, _)| *t == token)
+                .map(|(_, amount)| amount);
+            (token, amount + rest.sum::<U256>())
         }
-    };
-    Ok(SwapEntry {
-        pools,
-        token_in,
-        token_out,
-        amount_in,
-        amount_out,
+        None => (balance::consts::ETH, U256::ZERO),
+    }
+}
+
+/// (collection, recipient, id) of every NFT moved within `frame`. ERC-721
+/// `transferFrom` shares its selector with ERC-20's, so only transfers whose
+/// collection logged the id as an indexed topic are kept.
+fn nft_transfers(
+    trace: &[TransactionTrace],
+    frame: &TransactionTrace,
+    logs: &[Log<LogData>],
+) -> Vec<(Address, Address, U256)> {
+    use NonFungible::NonFungibleCalls as N;
+    frame_calls(trace, frame)
+        .filter_map(|call| Some((call.to, N::abi_decode(&call.input, true).ok()?)))
+        .flat_map(|(collection, transfer)| {
+            let (to, ids) = match transfer {
+                N::transferFrom(call) if is_nft_transfer(logs, &collection, &call.tokenId) => {
+                    (call.to, vec![call.tokenId])
+                }
+                N::safeTransferFrom_0(call) => (call.to, vec![call.tokenId]),
+                N::safeTransferFrom_1(call) => (call.to, vec![call.tokenId]),
+                N::safeTransferFrom_2(call) => (call.to, vec![call.id]),
+                N::safeBatchTransferFrom(call) => (call.to, call.ids),
+                _ => (Address::ZERO, vec![]),
+            };
+            ids.into_iter().map(move |id| (collection, to, id))
+        })
+        .collect()
+}
+
+/// Groups the (recipient, swap) pairs of an `execute` into independent
+/// swaps, in command order. A swap joins the group before it when it is
+/// another split of the group's last step, to the same recipient, or when it
+/// spends the group's output left in the router; anything else starts a new
+/// group.
+/// Each group keeps the recipient of its last swap.
+fn chain_swaps(swaps: Vec<(Address, SwapEntry)>) -> Vec<(Address, Vec<SwapEntry>)> {
+    let mut groups: Vec<(Address, Vec<SwapEntry>)> = vec![];
+    for (recipient, swap) in swaps {
+        if let Some((group_recipient, group)) = groups.last_mut() {
+            let last = &group[group.len() - 1];
+            let split = swap.token_in == last.token_in
+                && swap.token_out == last.token_out
+                && recipient == *group_recipient;
+            let chained =
+                *group_recipient == consts::ADDRESS_THIS && swap.token_in == last.token_out;
+            if split || chained {
+                *group_recipient = recipient;
+                group.push(swap);
+                continue;
+            }
+        }
+        groups.push((recipient, vec![swap]));
+    }
+    groups
+}
+
+/// The user intent of a router call made of several swaps: split routes are
+/// summed up and the output goes wherever the last swap sends it.
+pub(crate) fn net_swap(
+    from_address: Address,
+    to_address: Address,
+    swaps: Vec<SwapEntry>,
+) -> Option<super::Swap> {
+    let (input_token, output_token) = (swaps.first()?.token_in, swaps.last()?.token_out);
+    let input_amount = swaps
+        .iter()
+        .filter(|swap| swap.token_in == input_token)
+        .map(|swap| swap.amount_in)
+        .sum();
+    let output_amount = swaps
+        .iter()
+        .filter(|swap| swap.token_out == output_token)
+        .map(|swap| swap.amount_out)
+        .sum();
+    Some(super::Swap {
+        legs: swaps.into_iter().flat_map(|swap| swap.legs).collect(),
+        ..super::Swap::new(
+            from_address,
+            to_address,
+            input_token,
+            output_token,
+            input_amount,
+            output_amount,
+        )
     })
 }
 
-fn v2_decode_swap(
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SwapEntry {
+    pub(crate) legs: Vec<SwapLeg>,
+    pub(crate) token_in: Address,
+    pub(crate) token_out: Address,
+    pub(crate) amount_in: U256,
+    pub(crate) amount_out: U256,
+}
+
+impl SwapEntry {
+    pub(crate) fn from_legs(legs: Vec<SwapLeg>) -> Result<Self, DecodeError> {
+        let (first, last) = match (legs.first(), legs.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(DecodeError::EmptyPath),
+        };
+        Ok(Self {
+            token_in: first.token_in,
+            token_out: last.token_out,
+            amount_in: first.amount_in,
+            amount_out: last.amount_out,
+            legs,
+        })
+    }
+}
+
+pub(crate) enum SwapType {
+    ExactIn,
+    ExactOut,
+}
+
+pub(crate) fn v3_decode_swap(
     router: &Address,
     swap_type: SwapType,
-    path: &Vec<Address>,
+    path: &Bytes,
     logs: &[Log<LogData>],
-) -> eyre::Result<SwapEntry> {
-    let analyze_swap = |pool: &Address| -> eyre::Result<U256> {
-        let swap_logs = logs
-            .iter()
-            .filter(|log| log.address() == *pool && !log.removed)
-            .filter_map(|log| {
-                let swap = Dispatcher::Swap_1::decode_log(&log.inner, true).ok()?;
-                match swap.sender == *router {
-                    true => Some((swap.amount0Out, swap.amount1Out)),
-                    false => None,
-                }
-            })
-            .collect::<Vec<_>>();
-        if swap_logs.len() != 1 {
-            return Err(eyre!("multiple pool calls not supported"));
-        }
-        let (amount_0, amount_1) = swap_logs[0];
-        Ok(if amount_0.is_zero() {
-            amount_0
-        } else {
-            amount_1
+) -> Result<SwapEntry, DecodeError> {
+    let mut pools = v3_decode_path(path)?;
+    // exact output paths are encoded from the output token backwards
+    if let SwapType::ExactOut = swap_type {
+        pools.reverse();
+        pools.iter_mut().for_each(Pool::flip);
+    }
+    let legs = pools
+        .iter()
+        .map(|pool| v3_decode_leg(router, pool, logs))
+        .collect::<Result<Vec<_>, _>>()?;
+    SwapEntry::from_legs(legs)
+}
+
+pub(crate) fn v3_decode_leg(
+    router: &Address,
+    pool: &Pool,
+    logs: &[Log<LogData>],
+) -> Result<SwapLeg, DecodeError> {
+    let swap_logs = logs
+        .iter()
+        .filter(|log| log.address() == pool.pool && !log.removed)
+        .filter_map(|log| {
+            let swap = balance::decode_event::<Dispatcher::Swap_0>(log)?;
+            match swap.sender == *router {
+                true => Some((swap.amount0, swap.amount1)),
+                false => None,
+            }
         })
+        .collect::<Vec<_>>();
+    match swap_logs.len() {
+        0 => return Err(DecodeError::MissingLogs(pool.pool)),
+        1 => {}
+        _ => return Err(DecodeError::AmbiguousPoolLogs(pool.pool)),
+    }
+    let (amount_0, amount_1) = swap_logs[0];
+    Ok(v3_leg(pool, amount_0, amount_1))
+}
+
+/// Builds a V3 leg from the pool's signed deltas, as found in its `Swap`
+/// event or in the `uniswapV3SwapCallback` it makes.
+pub(crate) fn v3_leg(pool: &Pool, amount_0: I256, amount_1: I256) -> SwapLeg {
+    // positive amounts are paid into the pool, negative ones are paid out
+    let zero_for_one = !pool.reverse;
+    let (amount_in, amount_out) = match zero_for_one {
+        true => (amount_0, amount_1),
+        false => (amount_1, amount_0),
     };
+    SwapLeg {
+        protocol: Protocol::UniswapV3,
+        pool: pool.pool,
+        fee: pool.fee,
+        token_in: pool.token_in,
+        token_out: pool.token_out,
+        amount_in: amount_in.unsigned_abs(),
+        amount_out: amount_out.unsigned_abs(),
+        zero_for_one,
+    }
+}
 
-    let pools = path
+pub(crate) fn v2_decode_swap(
+    router: &Address,
+    swap_type: SwapType,
+    path: &[Address],
+    logs: &[Log<LogData>],
+) -> Result<SwapEntry, DecodeError> {
+    let legs = path
         .windows(2)
         .map(|a| {
             let (token_in, token_out) = (a[0], a[1]);
-            Pool {
+            let pool = Pool {
                 token_in,
                 token_out,
                 fee: 0,
-                pool: v2_compute_pool_address(token_in, token_out, None, None),
+                pool: v2_compute_pool_address(token_in, token_out, None, None)?,
                 reverse: token_in > token_out,
+            };
+            v2_decode_leg(router, &pool, logs)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    SwapEntry::from_legs(legs)
+}
+
+pub(crate) fn v2_decode_leg(
+    router: &Address,
+    pool: &Pool,
+    logs: &[Log<LogData>],
+) -> Result<SwapLeg, DecodeError> {
+    let swap_logs = logs
+        .iter()
+        .filter(|log| log.address() == pool.pool && !log.removed)
+        .filter_map(|log| {
+            let swap = balance::decode_event::<Dispatcher::Swap_1>(log)?;
+            match swap.sender == *router {
+                true => Some(swap),
+                false => None,
             }
         })
         .collect::<Vec<_>>();
-    if pools.is_empty() {
-        return Err(eyre!("no path found"));
+    match swap_logs.len() {
+        0 => return Err(DecodeError::MissingLogs(pool.pool)),
+        1 => {}
+        _ => return Err(DecodeError::AmbiguousPoolLogs(pool.pool)),
     }
-    let (token_in, token_out) = (
-        pools.first().unwrap().token_in,
-        pools.last().unwrap().token_out,
-    );
-    let (amount_in, amount_out) = match swap_type {
-        SwapType::ExactIn(amount_in) => {
-            let amount_out = analyze_swap(&pools.last().unwrap().pool)?;
-            (amount_in, amount_out)
-        }
-        SwapType::ExactOut(amount_out) => {
-            let amount_in = analyze_swap(&pools.first().unwrap().pool)?;
-            (amount_in, amount_out)
-        }
+    let zero_for_one = !pool.reverse;
+    let swap = &swap_logs[0];
+    let (amount_in, amount_out) = match zero_for_one {
+        true => (swap.amount0In, swap.amount1Out),
+        false => (swap.amount1In, swap.amount0Out),
     };
-    Ok(SwapEntry {
-        pools,
-        token_in,
-        token_out,
+    Ok(SwapLeg {
+        protocol: Protocol::UniswapV2,
+        pool: pool.pool,
+        fee: pool.fee,
+        token_in: pool.token_in,
+        token_out: pool.token_out,
         amount_in,
         amount_out,
+        zero_for_one,
     })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct Pool {
-    token_in: Address,
-    token_out: Address,
-    fee: u32,
-    pool: Address,
-    reverse: bool, // if true, it means token_in > token_out
+pub(crate) struct Pool {
+    pub(crate) token_in: Address,
+    pub(crate) token_out: Address,
+    pub(crate) fee: u32,
+    pub(crate) pool: Address,
+    pub(crate) reverse: bool, // if true, it means token_in > token_out
 }
 
 impl Pool {
+    fn flip(&mut self) {
+        std::mem::swap(&mut self.token_in, &mut self.token_out);
+        self.reverse = !self.reverse;
+    }
+
     fn token_0(&self) -> Address {
         match self.reverse {
             true => self.token_out,
@@ -442,10 +915,17 @@ impl Pool {
     }
 }
 
-fn v3_decode_path(path: &Bytes) -> Vec<Pool> {
+/// Splits a packed `token (fee token)+` path into its pools, rejecting paths
+/// that are not at least one hop of whole `fee token` steps.
+fn v3_decode_path(path: &Bytes) -> Result<Vec<Pool>, DecodeError> {
     const ADDR_SIZE: usize = 20;
     const FEE_SIZE: usize = 3;
 
+    if path.len() < ADDR_SIZE * 2 + FEE_SIZE
+        || !(path.len() - ADDR_SIZE).is_multiple_of(ADDR_SIZE + FEE_SIZE)
+    {
+        return Err(DecodeError::EmptyPath);
+    }
     let mut result = vec![];
     let mut offset = 0;
     loop {
@@ -457,11 +937,10 @@ fn v3_decode_path(path: &Bytes) -> Vec<Pool> {
         let fee = {
             let mut buf = [0u8; 4];
             fee_buf.copy_to_slice(&mut buf[1..]);
-            let fee = u32::from_be_bytes(buf);
-            fee
+            u32::from_be_bytes(buf)
         };
 
-        let pool = v3_compute_pool_address(token_in, token_out, fee, None, None);
+        let pool = v3_compute_pool_address(token_in, token_out, fee, None, None)?;
         result.push(Pool {
             token_in,
             token_out,
@@ -475,27 +954,38 @@ fn v3_decode_path(path: &Bytes) -> Vec<Pool> {
             break;
         }
     }
-    result
+    Ok(result)
 }
 
-fn v3_compute_pool_address(
+/// Fee tier of a canonical Uniswap V3 `pool` between the two tokens, 0 when
+/// the address doesn't match any tier (e.g. a fork's pool).
+pub(crate) fn v3_pool_fee(token_a: Address, token_b: Address, pool: &Address) -> u32 {
+    [100, 500, 3000, 10000]
+        .into_iter()
+        .find(|fee| v3_compute_pool_address(token_a, token_b, *fee, None, None).ok() == Some(*pool))
+        .unwrap_or_default()
+}
+
+pub(crate) fn v3_compute_pool_address(
     token_a: Address,
     token_b: Address,
     fee: u32,
     factory: Option<Address>,
     init_code_hash: Option<B256>,
-) -> Address {
-    assert_ne!(token_a, token_b, "TOKEN ADDRESS");
+) -> Result<Address, DecodeError> {
+    if token_a == token_b {
+        return Err(DecodeError::UnresolvedPair(token_a, token_b));
+    }
     let (token_0, token_1) = if token_a < token_b {
         (token_a, token_b)
     } else {
         (token_b, token_a)
     };
     let pool_key = (token_0, token_1, fee);
-    factory.unwrap_or(consts::V3_FACTORY_ADDRESS).create2(
+    Ok(factory.unwrap_or(consts::V3_FACTORY_ADDRESS).create2(
         keccak256(pool_key.abi_encode()),
         init_code_hash.unwrap_or(consts::V3_POOL_INIT_CODE_HASH),
-    )
+    ))
 }
 
 fn v2_compute_pool_address(
@@ -503,16 +993,141 @@ fn v2_compute_pool_address(
     token_b: Address,
     factory: Option<Address>,
     init_code_hash: Option<B256>,
-) -> Address {
-    assert_ne!(token_a, token_b, "TOKEN ADDRESS");
+) -> Result<Address, DecodeError> {
+    if token_a == token_b {
+        return Err(DecodeError::UnresolvedPair(token_a, token_b));
+    }
     let (token_0, token_1) = if token_a < token_b {
         (token_a, token_b)
     } else {
         (token_b, token_a)
     };
     let pool_key = (token_0, token_1);
-    factory.unwrap_or(consts::V2_FACTORY_ADDRESS).create2(
+    Ok(factory.unwrap_or(consts::V2_FACTORY_ADDRESS).create2(
         keccak256(pool_key.abi_encode_packed()),
         init_code_hash.unwrap_or(consts::V2_PAIR_INIT_CODE_HASH),
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{bytes, hex};
+
+    use super::*;
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    const WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+    const DAI: Address = address!("6B175474E89094C44Da98b954EedeAC495271d0F");
+    const USER: Address = address!("00000000000000000000000000000000000000aa");
+
+    fn entry(token_in: Address, token_out: Address) -> SwapEntry {
+        SwapEntry {
+            legs: vec![],
+            token_in,
+            token_out,
+            amount_in: U256::from(1),
+            amount_out: U256::from(1),
+        }
+    }
+
+    #[test]
+    fn pool_addresses() {
+        assert_eq!(
+            v3_compute_pool_address(USDC, WETH, 500, None, None).unwrap(),
+            address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")
+        );
+        assert_eq!(
+            v2_compute_pool_address(WETH, USDC, None, None).unwrap(),
+            address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")
+        );
+        assert!(matches!(
+            v2_compute_pool_address(WETH, WETH, None, None),
+            Err(DecodeError::UnresolvedPair(_, _))
+        ));
+        assert_eq!(
+            v3_pool_fee(
+                WETH,
+                USDC,
+                &address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")
+            ),
+            500
+        );
+    }
+
+    #[test]
+    fn v3_path() {
+        // USDC -(0.05%)-> WETH
+        let path = bytes!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb480001f4c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+        let pools = v3_decode_path(&path).unwrap();
+        assert_eq!(pools.len(), 1);
+        assert_eq!((pools[0].token_in, pools[0].token_out), (USDC, WETH));
+        assert_eq!(pools[0].fee, 500);
+        assert!(!pools[0].reverse);
+
+        // a token short of a hop, and a dangling fee
+        for len in [20, 43 - 1, 43 + 3] {
+            let mut path = path.to_vec();
+            path.resize(len, 0);
+            assert!(matches!(
+                v3_decode_path(&path.into()),
+                Err(DecodeError::EmptyPath)
+            ));
+        }
+    }
+
+    #[test]
+    fn command_tables() {
+        use command_types::*;
+        // allow-revert flag masked off
+        assert_eq!(
+            command_type(&consts::ROUTER, 0x80 | v1_2::SEAPORT_V1_4),
+            0x20
+        );
+        assert_eq!(command_type(&consts::ROUTER_V2, 0x80 | SEAPORT), SEAPORT);
+
+        assert_eq!(nft_command(&consts::ROUTER, 0x11), Some(NftCommand::Market));
+        assert_eq!(
+            nft_command(&consts::ROUTER_V2, 0x11),
+            Some(NftCommand::Market721)
+        );
+        assert_eq!(nft_command(&consts::ROUTER, 0x14), None);
+        assert_eq!(
+            nft_command(&consts::ROUTER_V2, 0x14),
+            Some(NftCommand::Market1155)
+        );
+        assert_eq!(nft_command(&consts::ROUTER, 0x1e), Some(NftCommand::Market));
+        assert_eq!(nft_command(&consts::ROUTER_V2, 0x1e), None);
+        assert_eq!(nft_command(&consts::ROUTER, 0x20), Some(NftCommand::Market));
+        assert_eq!(nft_command(&consts::ROUTER, V3_SWAP_EXACT_IN), None);
+    }
+
+    #[test]
+    fn chained_swaps() {
+        let groups = chain_swaps(vec![
+            // two splits of USDC to WETH held by the router, swapped on to DAI
+            (consts::ADDRESS_THIS, entry(USDC, WETH)),
+            (consts::ADDRESS_THIS, entry(USDC, WETH)),
+            (USER, entry(WETH, DAI)),
+            (USER, entry(WETH, DAI)),
+            // unrelated to the trade before it
+            (USER, entry(DAI, USDC)),
+            (consts::MSG_SENDER, entry(DAI, USDC)),
+        ]);
+        let groups = groups
+            .iter()
+            .map(|(recipient, swaps)| (*recipient, swaps.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![(USER, 4), (USER, 1), (consts::MSG_SENDER, 1)]);
+
+        let (recipient, swaps) = chain_swaps(vec![
+            (consts::ADDRESS_THIS, entry(USDC, WETH)),
+            (consts::ADDRESS_THIS, entry(USDC, WETH)),
+            (USER, entry(WETH, DAI)),
+        ])
+        .remove(0);
+        let swap = net_swap(USER, recipient, swaps).unwrap();
+        assert_eq!((swap.input_token, swap.output_token), (USDC, DAI));
+        assert_eq!(swap.input_amount, U256::from(2));
+        assert_eq!(swap.output_amount, U256::from(1));
+    }
 }
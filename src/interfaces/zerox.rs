@@ -1,4 +1,41 @@
-use alloy::{rpc::types::Transaction, sol};
+use alloy::{
+    primitives::{Address, Bytes, B256, U256},
+    providers::network::TransactionResponse,
+    rpc::types::{trace::parity::Action, Log},
+    sol,
+    sol_types::{SolCall, SolEvent, SolInterface, SolValue},
+};
+
+use super::{
+    balance::{self, decode_event, is_eth},
+    extract_selector,
+    universal_router::{v2_decode_leg, v3_decode_swap, Pool, SwapType},
+    DecodeError, Decoder, DecoderContext, Fee, RouteSplit, Swap, SwapLeg,
+};
+
+mod consts {
+    use alloy::primitives::{address, Address};
+
+    pub const NAME: &str = "0x Exchange Proxy";
+    pub const EXCHANGE_PROXY: Address = address!("Def1C0ded9bec7F1a1670819833240f027b25EfF");
+
+    pub const PROXY_NAME: &str = "ZeroExProxy";
+    pub const PROXY: Address = address!("e66B31678d6C16E9ebf358268a790B763C133750");
+
+    // nonces of the transformers created by the TransformerDeployer at
+    // 0x39dCe47a67aD34344EAB877eaE3Ef1FA2a1d50Bb, FillQuoteTransformer is
+    // redeployed with every new liquidity source and is recognised by its data
+    pub const WETH_TRANSFORMER_NONCE: u32 = 6;
+    pub const PAY_TAKER_TRANSFORMER_NONCE: u32 = 7;
+    pub const AFFILIATE_FEE_TRANSFORMER_NONCE: u32 = 8;
+    pub const POSITIVE_SLIPPAGE_FEE_TRANSFORMER_NONCE: u32 = 29;
+
+    pub const PERCENT_BASE: u32 = 10_000;
+
+    // `MultiplexSubcall` ids with hops we decode
+    pub const MULTIPLEX_UNISWAP_V2: u8 = 3;
+    pub const MULTIPLEX_UNISWAP_V3: u8 = 4;
+}
 
 sol! {
 
@@ -95,6 +132,1031 @@ interface ZeroExProxy {
 
 }
 
-pub fn get_swap_info(tx: Transaction) -> eyre::Result<()> {
-    Ok(())
-}
\ No newline at end of file
+sol! {
+
+// `Transformation.data` of each transformer
+
+interface FillQuoteTransformer {
+    struct BridgeOrder {
+        bytes32 source;
+        uint256 takerTokenAmount;
+        uint256 makerTokenAmount;
+        bytes bridgeData;
+    }
+
+    struct LimitOrder {
+        address makerToken;
+        address takerToken;
+        uint128 makerAmount;
+        uint128 takerAmount;
+        uint128 takerTokenFeeAmount;
+        address maker;
+        address taker;
+        address sender;
+        address feeRecipient;
+        bytes32 pool;
+        uint64 expiry;
+        uint256 salt;
+    }
+
+    struct LimitOrderInfo {
+        LimitOrder order;
+        Signature signature;
+        uint256 maxTakerTokenFillAmount;
+    }
+
+    struct OtcOrder {
+        address makerToken;
+        address takerToken;
+        uint128 makerAmount;
+        uint128 takerAmount;
+        address maker;
+        address taker;
+        address txOrigin;
+        uint256 expiryAndNonce;
+    }
+
+    struct OtcOrderInfo {
+        OtcOrder order;
+        Signature signature;
+        uint256 maxTakerTokenFillAmount;
+    }
+
+    struct RfqOrder {
+        address makerToken;
+        address takerToken;
+        uint128 makerAmount;
+        uint128 takerAmount;
+        address maker;
+        address taker;
+        address txOrigin;
+        bytes32 pool;
+        uint64 expiry;
+        uint256 salt;
+    }
+
+    struct RfqOrderInfo {
+        RfqOrder order;
+        Signature signature;
+        uint256 maxTakerTokenFillAmount;
+    }
+
+    struct Signature {
+        uint8 signatureType;
+        uint8 v;
+        bytes32 r;
+        bytes32 s;
+    }
+
+    struct TransformData {
+        uint8 side;
+        address sellToken;
+        address buyToken;
+        BridgeOrder[] bridgeOrders;
+        LimitOrderInfo[] limitOrders;
+        RfqOrderInfo[] rfqOrders;
+        uint8[] fillSequence;
+        uint256 fillAmount;
+        address refundReceiver;
+        OtcOrderInfo[] otcOrders;
+    }
+}
+
+interface WethTransformer {
+    struct TransformData {
+        address token;
+        uint256 amount;
+    }
+}
+
+interface PayTakerTransformer {
+    struct TransformData {
+        address[] tokens;
+        uint256[] amounts;
+    }
+}
+
+interface AffiliateFeeTransformer {
+    struct TokenFee {
+        address token;
+        uint256 amount;
+        address payable recipient;
+    }
+}
+
+interface PositiveSlippageFeeTransformer {
+    struct TokenFee {
+        address token;
+        uint256 bestCaseAmount;
+        address recipient;
+    }
+}
+
+}
+
+sol! {
+
+// native features of the exchange proxy, next to `transformERC20`
+
+interface UniswapFeature {
+    function sellToUniswap(address[] calldata tokens, uint256 sellAmount, uint256 minBuyAmount, bool isSushi)
+        external
+        payable
+        returns (uint256 buyAmount);
+}
+
+interface UniswapV3Feature {
+    function sellEthForTokenToUniswapV3(bytes memory encodedPath, uint256 minBuyAmount, address recipient)
+        external
+        payable
+        returns (uint256 buyAmount);
+    function sellTokenForEthToUniswapV3(
+        bytes memory encodedPath,
+        uint256 sellAmount,
+        uint256 minBuyAmount,
+        address payable recipient
+    ) external returns (uint256 buyAmount);
+    function sellTokenForTokenToUniswapV3(
+        bytes memory encodedPath,
+        uint256 sellAmount,
+        uint256 minBuyAmount,
+        address recipient
+    ) external returns (uint256 buyAmount);
+}
+
+interface MultiplexFeature {
+    type MultiplexSubcall is uint8;
+
+    struct BatchSellSubcall {
+        MultiplexSubcall id;
+        uint256 sellAmount;
+        bytes data;
+    }
+
+    struct MultiHopSellSubcall {
+        MultiplexSubcall id;
+        bytes data;
+    }
+
+    function multiplexBatchSellEthForToken(address outputToken, BatchSellSubcall[] calldata calls, uint256 minBuyAmount)
+        external
+        payable
+        returns (uint256 boughtAmount);
+    function multiplexBatchSellTokenForEth(
+        address inputToken,
+        BatchSellSubcall[] calldata calls,
+        uint256 sellAmount,
+        uint256 minBuyAmount
+    ) external returns (uint256 boughtAmount);
+    function multiplexBatchSellTokenForToken(
+        address inputToken,
+        address outputToken,
+        BatchSellSubcall[] calldata calls,
+        uint256 sellAmount,
+        uint256 minBuyAmount
+    ) external returns (uint256 boughtAmount);
+    function multiplexMultiHopSellEthForToken(
+        address[] calldata tokens,
+        MultiHopSellSubcall[] calldata calls,
+        uint256 minBuyAmount
+    ) external payable returns (uint256 boughtAmount);
+    function multiplexMultiHopSellTokenForEth(
+        address[] calldata tokens,
+        MultiHopSellSubcall[] calldata calls,
+        uint256 sellAmount,
+        uint256 minBuyAmount
+    ) external returns (uint256 boughtAmount);
+    function multiplexMultiHopSellTokenForToken(
+        address[] calldata tokens,
+        MultiHopSellSubcall[] calldata calls,
+        uint256 sellAmount,
+        uint256 minBuyAmount
+    ) external returns (uint256 boughtAmount);
+}
+
+interface OtcOrdersFeature {
+    struct OtcOrder {
+        address makerToken;
+        address takerToken;
+        uint128 makerAmount;
+        uint128 takerAmount;
+        address maker;
+        address taker;
+        address txOrigin;
+        uint256 expiryAndNonce;
+    }
+
+    struct Signature {
+        uint8 signatureType;
+        uint8 v;
+        bytes32 r;
+        bytes32 s;
+    }
+
+    event OtcOrderFilled(
+        bytes32 orderHash,
+        address maker,
+        address taker,
+        address makerToken,
+        address takerToken,
+        uint128 makerTokenFilledAmount,
+        uint128 takerTokenFilledAmount
+    );
+
+    function fillOtcOrder(OtcOrder memory order, Signature memory makerSignature, uint128 takerTokenFillAmount)
+        external
+        returns (uint128 takerTokenFilledAmount, uint128 makerTokenFilledAmount);
+    function fillOtcOrderForEth(OtcOrder memory order, Signature memory makerSignature, uint128 takerTokenFillAmount)
+        external
+        returns (uint128 takerTokenFilledAmount, uint128 makerTokenFilledAmount);
+    function fillOtcOrderWithEth(OtcOrder memory order, Signature memory makerSignature)
+        external
+        payable
+        returns (uint128 takerTokenFilledAmount, uint128 makerTokenFilledAmount);
+    function fillTakerSignedOtcOrder(
+        OtcOrder memory order,
+        Signature memory makerSignature,
+        Signature memory takerSignature
+    ) external;
+    function fillTakerSignedOtcOrderForEth(
+        OtcOrder memory order,
+        Signature memory makerSignature,
+        Signature memory takerSignature
+    ) external;
+}
+
+interface NativeOrdersFeature {
+    struct RfqOrder {
+        address makerToken;
+        address takerToken;
+        uint128 makerAmount;
+        uint128 takerAmount;
+        address maker;
+        address taker;
+        address txOrigin;
+        bytes32 pool;
+        uint64 expiry;
+        uint256 salt;
+    }
+
+    struct Signature {
+        uint8 signatureType;
+        uint8 v;
+        bytes32 r;
+        bytes32 s;
+    }
+
+    event RfqOrderFilled(
+        bytes32 orderHash,
+        address maker,
+        address taker,
+        address makerToken,
+        address takerToken,
+        uint128 takerTokenFilledAmount,
+        uint128 makerTokenFilledAmount,
+        bytes32 pool
+    );
+
+    function fillRfqOrder(RfqOrder memory order, Signature memory signature, uint128 takerTokenFillAmount)
+        external
+        returns (uint128 takerTokenFilledAmount, uint128 makerTokenFilledAmount);
+}
+
+}
+
+pub struct DecoderZeroEx {}
+
+impl DecoderZeroEx {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Decoder for DecoderZeroEx {
+    fn name(&self) -> String {
+        consts::NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::EXCHANGE_PROXY]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        vec![
+            TransformERC20Feature::transformERC20Call::SELECTOR,
+            UniswapFeature::sellToUniswapCall::SELECTOR,
+            UniswapV3Feature::sellEthForTokenToUniswapV3Call::SELECTOR,
+            UniswapV3Feature::sellTokenForEthToUniswapV3Call::SELECTOR,
+            UniswapV3Feature::sellTokenForTokenToUniswapV3Call::SELECTOR,
+            MultiplexFeature::multiplexBatchSellEthForTokenCall::SELECTOR,
+            MultiplexFeature::multiplexBatchSellTokenForEthCall::SELECTOR,
+            MultiplexFeature::multiplexBatchSellTokenForTokenCall::SELECTOR,
+            MultiplexFeature::multiplexMultiHopSellEthForTokenCall::SELECTOR,
+            MultiplexFeature::multiplexMultiHopSellTokenForEthCall::SELECTOR,
+            MultiplexFeature::multiplexMultiHopSellTokenForTokenCall::SELECTOR,
+            OtcOrdersFeature::fillOtcOrderCall::SELECTOR,
+            OtcOrdersFeature::fillOtcOrderForEthCall::SELECTOR,
+            OtcOrdersFeature::fillOtcOrderWithEthCall::SELECTOR,
+            OtcOrdersFeature::fillTakerSignedOtcOrderCall::SELECTOR,
+            OtcOrdersFeature::fillTakerSignedOtcOrderForEthCall::SELECTOR,
+            NativeOrdersFeature::fillRfqOrderCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        let selector = extract_selector(&context.tx().input)?;
+        let swap = match selector {
+            s if TransformERC20Feature::TransformERC20FeatureCalls::valid_selector(s) => {
+                transform_erc20_decode_swap(context)?
+            }
+            s if UniswapFeature::UniswapFeatureCalls::valid_selector(s) => {
+                uniswap_decode_swap(context)?
+            }
+            s if UniswapV3Feature::UniswapV3FeatureCalls::valid_selector(s) => {
+                uniswap_v3_decode_swap(context)?
+            }
+            s if MultiplexFeature::MultiplexFeatureCalls::valid_selector(s) => {
+                multiplex_decode_swap(context)?
+            }
+            s if OtcOrdersFeature::OtcOrdersFeatureCalls::valid_selector(s) => {
+                otc_order_decode_swap(context)?
+            }
+            s if NativeOrdersFeature::NativeOrdersFeatureCalls::valid_selector(s) => {
+                rfq_order_decode_swap(context)?
+            }
+            _ => return Err(DecodeError::UnsupportedSelector(selector)),
+        };
+        Ok(vec![swap])
+    }
+}
+
+/// The fee-charging `ZeroExProxy`, which takes its fee and forwards a full
+/// exchange proxy call in `msgData`.
+pub struct DecoderZeroExProxy {
+    zero_ex: DecoderZeroEx,
+}
+
+impl DecoderZeroExProxy {
+    pub fn new() -> Self {
+        Self {
+            zero_ex: DecoderZeroEx::new(),
+        }
+    }
+}
+
+impl Decoder for DecoderZeroExProxy {
+    fn name(&self) -> String {
+        consts::PROXY_NAME.to_string()
+    }
+
+    fn supported_address(&self) -> Vec<Address> {
+        vec![consts::PROXY]
+    }
+
+    fn supported_selectors(&self) -> Vec<[u8; 4]> {
+        vec![
+            ZeroExProxy::proxiedSwapCall::SELECTOR,
+            ZeroExProxy::optimalSwapCall::SELECTOR,
+        ]
+    }
+
+    fn decode(&self, context: &DecoderContext) -> Result<Vec<Swap>, DecodeError> {
+        use ZeroExProxy::ZeroExProxyCalls as C;
+        let tx = context.tx();
+        let caller = tx.from();
+        let (msg_data, fee_token, fee) = match C::abi_decode(&tx.input, true)? {
+            C::proxiedSwap(call) => (call.msgData, call.feeToken, call.fee),
+            C::optimalSwap(call) => (call.msgData, call.feeToken, call.fee),
+            call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+        };
+        // an ETH fee is kept out of the value the proxy forwards
+        let value = match is_eth(&fee_token) {
+            true => tx.value().saturating_sub(fee),
+            false => tx.value(),
+        };
+        let inner = context.with_call(consts::PROXY, consts::EXCHANGE_PROXY, msg_data, value);
+        let mut swaps = self.zero_ex.decode(&inner)?;
+
+        let recipient = fee_recipient(context, fee_token, fee)?;
+        for swap in &mut swaps {
+            // the proxy only passes the tokens through
+            for address in [&mut swap.from_address, &mut swap.to_address] {
+                if *address == consts::PROXY {
+                    *address = caller;
+                }
+            }
+            // the inner swap was settled against the proxy's balances, what
+            // the caller paid and got also covers the fee
+            swap.mismatches.clear();
+            context.balance_deltas()?.settle(swap);
+            if let Some(recipient) = recipient {
+                swap.fees.push(Fee {
+                    recipient,
+                    token: fee_token,
+                    amount: fee,
+                });
+            }
+        }
+        Ok(swaps)
+    }
+}
+
+/// Where the proxy sent its `fee` of `fee_token`: an ERC-20 transfer of the
+/// fee, or a call carrying it as value for ETH. `None` without a fee or if
+/// neither is found.
+fn fee_recipient(
+    context: &DecoderContext,
+    fee_token: Address,
+    fee: U256,
+) -> Result<Option<Address>, DecodeError> {
+    if fee.is_zero() {
+        return Ok(None);
+    }
+    if !is_eth(&fee_token) {
+        return Ok(balance::transfers(context.logs()?)
+            .find(|(token, transfer)| {
+                *token == fee_token && transfer.from == consts::PROXY && transfer.value == fee
+            })
+            .map(|(_, transfer)| transfer.to));
+    }
+    Ok(context
+        .trace()?
+        .trace
+        .iter()
+        .filter(|trace| trace.error.is_none())
+        .find_map(|trace| match &trace.action {
+            Action::Call(call)
+                if call.from == consts::PROXY
+                    && call.to != consts::EXCHANGE_PROXY
+                    && call.value == fee =>
+            {
+                Some(call.to)
+            }
+            _ => None,
+        }))
+}
+
+fn transform_erc20_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use TransformERC20Feature::TransformERC20FeatureCalls as C;
+    let transformations = match C::abi_decode(&context.tx().input, true)? {
+        C::transformERC20(call) => call.transformations,
+        call => return Err(DecodeError::UnsupportedSelector(call.selector())),
+    };
+
+    // the proxy reports what the taker paid and got once every
+    // transformer has run
+    let transformed = context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::EXCHANGE_PROXY && !log.removed)
+        .find_map(decode_event::<TransformERC20Feature::TransformedERC20>)
+        .ok_or(DecodeError::MissingLogs(consts::EXCHANGE_PROXY))?;
+    let mut swap = Swap::new(
+        transformed.taker,
+        transformed.taker,
+        transformed.inputToken,
+        transformed.outputToken,
+        transformed.inputTokenAmount,
+        transformed.outputTokenAmount,
+    );
+
+    let deltas = context.balance_deltas()?;
+    let mut step = 0;
+    for transformation in &transformations {
+        match Transformer::decode(transformation)? {
+            Some(Transformer::FillQuote(data)) => {
+                swap.route.extend(fill_quote_route(step, &data));
+                step += 1;
+            }
+            // fees of `U256::MAX` sweep whatever the wallet holds
+            Some(Transformer::AffiliateFee(fees)) => {
+                swap.fees.extend(fees.into_iter().map(|fee| Fee {
+                    recipient: fee.recipient,
+                    token: fee.token,
+                    amount: match fee.amount == U256::MAX {
+                        true => deltas.received(&fee.recipient, &fee.token),
+                        false => fee.amount,
+                    },
+                }));
+            }
+            // only what the taker got above the quote is taken
+            Some(Transformer::PositiveSlippageFee(fee)) => swap.fees.push(Fee {
+                recipient: fee.recipient,
+                token: fee.token,
+                amount: deltas.received(&fee.recipient, &fee.token),
+            }),
+            // wrapping and the final payout are part of the proxy's event
+            Some(Transformer::Weth(_) | Transformer::PayTaker(_)) | None => {}
+        }
+    }
+    swap.fees.retain(|fee| !fee.amount.is_zero());
+    Ok(swap)
+}
+
+/// The decoded data of a `Transformation`.
+enum Transformer {
+    FillQuote(FillQuoteTransformer::TransformData),
+    Weth(WethTransformer::TransformData),
+    PayTaker(PayTakerTransformer::TransformData),
+    AffiliateFee(Vec<AffiliateFeeTransformer::TokenFee>),
+    PositiveSlippageFee(PositiveSlippageFeeTransformer::TokenFee),
+}
+
+impl Transformer {
+    /// `None` for transformers we don't know.
+    fn decode(
+        transformation: &TransformERC20Feature::Transformation,
+    ) -> Result<Option<Self>, DecodeError> {
+        use AffiliateFeeTransformer::TokenFee as AffiliateFee;
+        use PositiveSlippageFeeTransformer::TokenFee as PositiveSlippageFee;
+
+        let data = &transformation.data;
+        let transformer = match transformation.deploymentNonce {
+            consts::WETH_TRANSFORMER_NONCE => {
+                Self::Weth(WethTransformer::TransformData::abi_decode(data, true)?)
+            }
+            consts::PAY_TAKER_TRANSFORMER_NONCE => {
+                Self::PayTaker(PayTakerTransformer::TransformData::abi_decode(data, true)?)
+            }
+            consts::AFFILIATE_FEE_TRANSFORMER_NONCE => {
+                Self::AffiliateFee(Vec::<AffiliateFee>::abi_decode(data, true)?)
+            }
+            consts::POSITIVE_SLIPPAGE_FEE_TRANSFORMER_NONCE => {
+                Self::PositiveSlippageFee(PositiveSlippageFee::abi_decode(data, true)?)
+            }
+            _ => match FillQuoteTransformer::TransformData::abi_decode(data, true) {
+                Ok(data) => Self::FillQuote(data),
+                Err(_) => return Ok(None),
+            },
+        };
+        Ok(Some(transformer))
+    }
+}
+
+/// Splits a `FillQuoteTransformer` step between its orders, weighted by the
+/// taker amount each one is allowed to fill. Bridge orders are attributed to
+/// the first word of their `bridgeData`, the router or pool for most sources,
+/// native orders to their maker.
+fn fill_quote_route(step: usize, data: &FillQuoteTransformer::TransformData) -> Vec<RouteSplit> {
+    let bridge = data.bridgeOrders.iter().map(|order| {
+        let exchange = match order.bridgeData.get(..32) {
+            Some(word) => Address::from_word(B256::from_slice(word)),
+            None => Address::ZERO,
+        };
+        (exchange, order.takerTokenAmount)
+    });
+    let limit = data
+        .limitOrders
+        .iter()
+        .map(|info| (info.order.maker, info.maxTakerTokenFillAmount));
+    let rfq = data
+        .rfqOrders
+        .iter()
+        .map(|info| (info.order.maker, info.maxTakerTokenFillAmount));
+    let otc = data
+        .otcOrders
+        .iter()
+        .map(|info| (info.order.maker, info.maxTakerTokenFillAmount));
+    let orders = bridge
+        .chain(limit)
+        .chain(rfq)
+        .chain(otc)
+        .collect::<Vec<_>>();
+
+    let total = orders.iter().fold(U256::ZERO, |total, (_, amount)| {
+        total.saturating_add(*amount)
+    });
+    let base = U256::from(consts::PERCENT_BASE);
+    orders
+        .into_iter()
+        .map(|(exchange, amount)| RouteSplit {
+            path: 0,
+            step,
+            path_percent: consts::PERCENT_BASE,
+            percent: match total.is_zero() {
+                true => 0,
+                false => (amount.saturating_mul(base) / total).saturating_to(),
+            },
+            token_in: data.sellToken,
+            token_out: data.buyToken,
+            exchange,
+            legs: vec![],
+        })
+        .collect()
+}
+
+/// `sellToUniswap` trades a path of Uniswap V2 or SushiSwap pairs, with the
+/// native token at either end of `tokens` as ETH.
+fn uniswap_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    let tx = context.tx();
+    let call = UniswapFeature::sellToUniswapCall::abi_decode(&tx.input, true)?;
+    let tokens = path_ends(&call.tokens)?;
+    let legs = uniswap_v2_legs(context.logs()?, &call.tokens)?;
+    let output_amount = last_amount_out(&legs);
+    settle_swap(
+        context,
+        (tx.from(), tx.from()),
+        tokens,
+        (call.sellAmount, output_amount),
+        legs,
+    )
+}
+
+fn uniswap_v3_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use UniswapV3Feature::UniswapV3FeatureCalls as C;
+    let tx = context.tx();
+    let (path, recipient, input_amount) = match C::abi_decode(&tx.input, true)? {
+        C::sellEthForTokenToUniswapV3(call) => (call.encodedPath, call.recipient, tx.value()),
+        C::sellTokenForEthToUniswapV3(call) => (call.encodedPath, call.recipient, call.sellAmount),
+        C::sellTokenForTokenToUniswapV3(call) => {
+            (call.encodedPath, call.recipient, call.sellAmount)
+        }
+    };
+    let recipient = match recipient.is_zero() {
+        true => tx.from(),
+        false => recipient,
+    };
+    let entry = v3_decode_swap(
+        &consts::EXCHANGE_PROXY,
+        SwapType::ExactIn,
+        &path,
+        context.logs()?,
+    )?;
+    // WETH at the user's end is reported as ETH by `settle` when unwrapped
+    let tokens = (entry.token_in, entry.token_out);
+    let output_amount = last_amount_out(&entry.legs);
+    settle_swap(
+        context,
+        (tx.from(), recipient),
+        tokens,
+        (input_amount, output_amount),
+        entry.legs,
+    )
+}
+
+/// Multiplex calls route the sell amount through a batch of subcalls, each
+/// selling part of it, or through a chain of them, one per hop of `tokens`.
+/// Hops are decoded for the Uniswap subcalls, the others only count towards
+/// the settled amounts.
+fn multiplex_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use MultiplexFeature::MultiplexFeatureCalls as C;
+    let tx = context.tx();
+    let logs = context.logs()?;
+    let eth = balance::consts::ETH;
+    let subcalls = |calls: Vec<(u8, &Bytes)>| {
+        calls.into_iter().try_fold(vec![], |mut legs, (id, data)| {
+            legs.extend(multiplex_subcall_legs(logs, id, data)?);
+            Ok::<_, DecodeError>(legs)
+        })
+    };
+    let batch = |calls: &[MultiplexFeature::BatchSellSubcall]| {
+        subcalls(calls.iter().map(|call| (call.id, &call.data)).collect())
+    };
+    let multi_hop = |calls: &[MultiplexFeature::MultiHopSellSubcall]| {
+        subcalls(calls.iter().map(|call| (call.id, &call.data)).collect())
+    };
+
+    let (tokens, input_amount, legs) = match C::abi_decode(&tx.input, true)? {
+        C::multiplexBatchSellEthForToken(call) => {
+            ((eth, call.outputToken), tx.value(), batch(&call.calls)?)
+        }
+        C::multiplexBatchSellTokenForEth(call) => {
+            ((call.inputToken, eth), call.sellAmount, batch(&call.calls)?)
+        }
+        C::multiplexBatchSellTokenForToken(call) => (
+            (call.inputToken, call.outputToken),
+            call.sellAmount,
+            batch(&call.calls)?,
+        ),
+        C::multiplexMultiHopSellEthForToken(call) => {
+            let (_, output_token) = path_ends(&call.tokens)?;
+            ((eth, output_token), tx.value(), multi_hop(&call.calls)?)
+        }
+        C::multiplexMultiHopSellTokenForEth(call) => {
+            let (input_token, _) = path_ends(&call.tokens)?;
+            ((input_token, eth), call.sellAmount, multi_hop(&call.calls)?)
+        }
+        C::multiplexMultiHopSellTokenForToken(call) => {
+            let tokens = path_ends(&call.tokens)?;
+            (tokens, call.sellAmount, multi_hop(&call.calls)?)
+        }
+    };
+    // only the Uniswap subcalls have legs, so the bought amount is left to
+    // the balance changes
+    settle_swap(
+        context,
+        (tx.from(), tx.from()),
+        tokens,
+        (input_amount, U256::ZERO),
+        legs,
+    )
+}
+
+/// Hops of one multiplex subcall.
+fn multiplex_subcall_legs(logs: &[Log], id: u8, data: &Bytes) -> Result<Vec<SwapLeg>, DecodeError> {
+    let legs = match id {
+        consts::MULTIPLEX_UNISWAP_V2 => {
+            let (tokens, _is_sushi) = <(Vec<Address>, bool)>::abi_decode_params(data, true)?;
+            uniswap_v2_legs(logs, &tokens)?
+        }
+        consts::MULTIPLEX_UNISWAP_V3 => {
+            v3_decode_swap(&consts::EXCHANGE_PROXY, SwapType::ExactIn, data, logs)?.legs
+        }
+        _ => vec![],
+    };
+    Ok(legs)
+}
+
+fn otc_order_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    use OtcOrdersFeature::OtcOrdersFeatureCalls as C;
+    let tx = context.tx();
+    let eth = balance::consts::ETH;
+    let (order, eth_in, eth_out) = match C::abi_decode(&tx.input, true)? {
+        C::fillOtcOrder(call) => (call.order, false, false),
+        C::fillOtcOrderForEth(call) => (call.order, false, true),
+        C::fillOtcOrderWithEth(call) => (call.order, true, false),
+        C::fillTakerSignedOtcOrder(call) => (call.order, false, false),
+        C::fillTakerSignedOtcOrderForEth(call) => (call.order, false, true),
+    };
+    // taker signed orders are submitted on the taker's behalf, who is only
+    // known from the event
+    let filled = context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::EXCHANGE_PROXY && !log.removed)
+        .find_map(decode_event::<OtcOrdersFeature::OtcOrderFilled>)
+        .ok_or(DecodeError::MissingLogs(consts::EXCHANGE_PROXY))?;
+    let mut swap = Swap::new(
+        filled.taker,
+        filled.taker,
+        if eth_in { eth } else { order.takerToken },
+        if eth_out { eth } else { order.makerToken },
+        U256::from(filled.takerTokenFilledAmount),
+        U256::from(filled.makerTokenFilledAmount),
+    );
+    context.balance_deltas()?.settle(&mut swap);
+    Ok(swap)
+}
+
+fn rfq_order_decode_swap(context: &DecoderContext) -> Result<Swap, DecodeError> {
+    let call = NativeOrdersFeature::fillRfqOrderCall::abi_decode(&context.tx().input, true)?;
+    let order = call.order;
+    let filled = context
+        .logs()?
+        .iter()
+        .filter(|log| log.address() == consts::EXCHANGE_PROXY && !log.removed)
+        .find_map(decode_event::<NativeOrdersFeature::RfqOrderFilled>)
+        .ok_or(DecodeError::MissingLogs(consts::EXCHANGE_PROXY))?;
+    let mut swap = Swap::new(
+        filled.taker,
+        filled.taker,
+        order.takerToken,
+        order.makerToken,
+        U256::from(filled.takerTokenFilledAmount),
+        U256::from(filled.makerTokenFilledAmount),
+    );
+    context.balance_deltas()?.settle(&mut swap);
+    Ok(swap)
+}
+
+/// A swap settled against the balance changes, which fill in the bought
+/// amount when `output_amount` is zero.
+fn settle_swap(
+    context: &DecoderContext,
+    (from_address, to_address): (Address, Address),
+    (input_token, output_token): (Address, Address),
+    (input_amount, output_amount): (U256, U256),
+    legs: Vec<SwapLeg>,
+) -> Result<Swap, DecodeError> {
+    let mut swap = Swap {
+        legs,
+        ..Swap::new(
+            from_address,
+            to_address,
+            input_token,
+            output_token,
+            input_amount,
+            output_amount,
+        )
+    };
+    context.balance_deltas()?.settle(&mut swap);
+    Ok(swap)
+}
+
+fn last_amount_out(legs: &[SwapLeg]) -> U256 {
+    legs.last().map(|leg| leg.amount_out).unwrap_or_default()
+}
+
+/// Hops through the Uniswap V2 or SushiSwap pairs along `tokens`, which the
+/// proxy calls directly. The pairs are found from the transfers instead of
+/// being derived for each factory.
+fn uniswap_v2_legs(logs: &[Log], tokens: &[Address]) -> Result<Vec<SwapLeg>, DecodeError> {
+    tokens
+        .windows(2)
+        .map(|hop| {
+            let (token_in, token_out) = (pool_token(hop[0]), pool_token(hop[1]));
+            // the pair is paid `token_in` and pays out `token_out`
+            let pair = balance::transfers(logs)
+                .filter(|(token, _)| *token == token_out)
+                .map(|(_, transfer)| transfer.from)
+                .find(|from| {
+                    balance::transfers(logs)
+                        .any(|(token, transfer)| token == token_in && transfer.to == *from)
+                })
+                .ok_or(DecodeError::UnresolvedPair(token_in, token_out))?;
+            let pool = Pool {
+                token_in,
+                token_out,
+                fee: 0,
+                pool: pair,
+                reverse: token_in > token_out,
+            };
+            v2_decode_leg(&consts::EXCHANGE_PROXY, &pool, logs)
+        })
+        .collect()
+}
+
+/// Pools trade WETH where the proxy's calldata says ETH.
+fn pool_token(token: Address) -> Address {
+    match is_eth(&token) {
+        true => balance::consts::WETH,
+        false => token,
+    }
+}
+
+fn path_ends(tokens: &[Address]) -> Result<(Address, Address), DecodeError> {
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => Ok((*first, *last)),
+        _ => Err(DecodeError::EmptyPath),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    const WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+    const UNISWAP_V2_ROUTER: Address = address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+    const MAKER: Address = address!("00000000000000000000000000000000000000aa");
+    const RECIPIENT: Address = address!("00000000000000000000000000000000000000bb");
+
+    fn transformation(nonce: u32, data: Vec<u8>) -> TransformERC20Feature::Transformation {
+        TransformERC20Feature::Transformation {
+            deploymentNonce: nonce,
+            data: data.into(),
+        }
+    }
+
+    fn bridge_order(exchange: Option<Address>, amount: u64) -> FillQuoteTransformer::BridgeOrder {
+        let bridge_data = match exchange {
+            Some(exchange) => (exchange, U256::from(1)).abi_encode_params(),
+            None => vec![],
+        };
+        FillQuoteTransformer::BridgeOrder {
+            source: B256::ZERO,
+            takerTokenAmount: U256::from(amount),
+            makerTokenAmount: U256::ZERO,
+            bridgeData: bridge_data.into(),
+        }
+    }
+
+    fn rfq_order(maker: Address, amount: u64) -> FillQuoteTransformer::RfqOrderInfo {
+        FillQuoteTransformer::RfqOrderInfo {
+            order: FillQuoteTransformer::RfqOrder {
+                makerToken: WETH,
+                takerToken: USDC,
+                makerAmount: 0,
+                takerAmount: 0,
+                maker,
+                taker: Address::ZERO,
+                txOrigin: Address::ZERO,
+                pool: B256::ZERO,
+                expiry: 0,
+                salt: U256::ZERO,
+            },
+            signature: FillQuoteTransformer::Signature {
+                signatureType: 0,
+                v: 0,
+                r: B256::ZERO,
+                s: B256::ZERO,
+            },
+            maxTakerTokenFillAmount: U256::from(amount),
+        }
+    }
+
+    fn fill_quote(
+        bridge_orders: Vec<FillQuoteTransformer::BridgeOrder>,
+        rfq_orders: Vec<FillQuoteTransformer::RfqOrderInfo>,
+    ) -> FillQuoteTransformer::TransformData {
+        FillQuoteTransformer::TransformData {
+            side: 0,
+            sellToken: USDC,
+            buyToken: WETH,
+            bridgeOrders: bridge_orders,
+            limitOrders: vec![],
+            rfqOrders: rfq_orders,
+            fillSequence: vec![],
+            fillAmount: U256::from(500),
+            refundReceiver: Address::ZERO,
+            otcOrders: vec![],
+        }
+    }
+
+    #[test]
+    fn transformer_nonces() {
+        let weth = WethTransformer::TransformData {
+            token: WETH,
+            amount: U256::from(7),
+        };
+        let transformer = Transformer::decode(&transformation(
+            consts::WETH_TRANSFORMER_NONCE,
+            weth.abi_encode(),
+        ))
+        .unwrap();
+        assert!(
+            matches!(transformer, Some(Transformer::Weth(data)) if data.amount == U256::from(7))
+        );
+
+        let fees = vec![AffiliateFeeTransformer::TokenFee {
+            token: USDC,
+            amount: U256::from(3),
+            recipient: RECIPIENT,
+        }];
+        let transformer = Transformer::decode(&transformation(
+            consts::AFFILIATE_FEE_TRANSFORMER_NONCE,
+            fees.abi_encode(),
+        ))
+        .unwrap();
+        match transformer {
+            Some(Transformer::AffiliateFee(fees)) => {
+                assert_eq!(fees.len(), 1);
+                assert_eq!((fees[0].token, fees[0].recipient), (USDC, RECIPIENT));
+            }
+            _ => panic!("expected an affiliate fee"),
+        }
+
+        let fee = PositiveSlippageFeeTransformer::TokenFee {
+            token: WETH,
+            bestCaseAmount: U256::from(10),
+            recipient: RECIPIENT,
+        };
+        let transformer = Transformer::decode(&transformation(
+            consts::POSITIVE_SLIPPAGE_FEE_TRANSFORMER_NONCE,
+            fee.abi_encode(),
+        ))
+        .unwrap();
+        assert!(
+            matches!(transformer, Some(Transformer::PositiveSlippageFee(fee)) if fee.recipient == RECIPIENT)
+        );
+
+        // FillQuoteTransformer has no fixed nonce and is told apart by its data
+        let data = fill_quote(vec![bridge_order(Some(UNISWAP_V2_ROUTER), 1)], vec![]);
+        let transformer = Transformer::decode(&transformation(42, data.abi_encode())).unwrap();
+        assert!(matches!(transformer, Some(Transformer::FillQuote(data)) if data.buyToken == WETH));
+
+        // unknown transformers are skipped, known ones must decode
+        assert!(Transformer::decode(&transformation(42, vec![0x12, 0x34]))
+            .unwrap()
+            .is_none());
+        assert!(Transformer::decode(&transformation(
+            consts::PAY_TAKER_TRANSFORMER_NONCE,
+            vec![0x12, 0x34]
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn fill_quote_splits() {
+        let data = fill_quote(
+            vec![
+                bridge_order(Some(UNISWAP_V2_ROUTER), 300),
+                bridge_order(None, 100),
+            ],
+            vec![rfq_order(MAKER, 100)],
+        );
+        let route = fill_quote_route(2, &data);
+        let splits = route
+            .iter()
+            .map(|split| (split.exchange, split.percent))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            splits,
+            vec![
+                (UNISWAP_V2_ROUTER, 6000),
+                (Address::ZERO, 2000),
+                (MAKER, 2000)
+            ]
+        );
+        assert!(route.iter().all(|split| split.step == 2
+            && split.token_in == USDC
+            && split.token_out == WETH
+            && split.path_percent == consts::PERCENT_BASE));
+
+        // nothing to weigh the orders by
+        let data = fill_quote(vec![bridge_order(Some(UNISWAP_V2_ROUTER), 0)], vec![]);
+        assert_eq!(fill_quote_route(0, &data)[0].percent, 0);
+    }
+}
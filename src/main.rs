@@ -13,7 +13,7 @@ use polars::{lazy::prelude::*, prelude::*};
 
 mod interfaces;
 
-fn main() {
+fn main() -> eyre::Result<()> {
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
@@ -27,5 +27,7 @@ fn main() {
         "2852362de2c7c05050d7b8c10945aa6161f7bbdc34f136068c28205f32a8308a"
     ));
     let rpc = "http://192.168.0.105:8545".to_string();
-    interfaces::DecoderContext::decode(rpc, tx).expect("decode");
+    let decoded = interfaces::DecoderRegistry::default().decode(rpc, tx)?;
+    println!("{:#?}", decoded);
+    Ok(())
 }